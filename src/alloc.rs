@@ -0,0 +1,90 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use mimalloc::MiMalloc;
+
+const UNKNOWN: u8 = 0;
+const MIMALLOC: u8 = 1;
+const SYSTEM: u8 = 2;
+/// The system allocator, because mimalloc failed its first allocation
+const FALLBACK: u8 = 3;
+
+/// Global allocator that uses mimalloc, or the system allocator when `THUNDER_ALLOC=system`
+/// or when mimalloc can not allocate at all on this platform.
+/// The choice is made on the first allocation and costs an atomic load per call afterwards.
+pub struct Allocator(AtomicU8);
+
+impl Allocator {
+    pub const fn new() -> Self {
+        Self(AtomicU8::new(UNKNOWN))
+    }
+
+    /// Whether mimalloc failed and the system allocator is used instead
+    pub fn fell_back(&self) -> bool {
+        self.0.load(Ordering::Relaxed) == FALLBACK
+    }
+
+    #[inline]
+    fn system(&self) -> bool {
+        match self.0.load(Ordering::Relaxed) {
+            UNKNOWN => {
+                // getenv does not allocate, unlike std::env::var
+                let value = unsafe { nix::libc::getenv(c"THUNDER_ALLOC".as_ptr()) };
+                let system = !value.is_null()
+                    && unsafe { std::ffi::CStr::from_ptr(value) }.to_bytes() == b"system";
+                let state = if system { SYSTEM } else { Self::probe() };
+                self.0.store(state, Ordering::Relaxed);
+                state != MIMALLOC
+            }
+            state => state != MIMALLOC,
+        }
+    }
+
+    /// Try a small mimalloc allocation, before anything else was allocated
+    #[cold]
+    fn probe() -> u8 {
+        let layout = Layout::new::<usize>();
+        let ptr = unsafe { MiMalloc.alloc(layout) };
+        if ptr.is_null() {
+            return FALLBACK;
+        }
+        unsafe { MiMalloc.dealloc(ptr, layout) };
+        MIMALLOC
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.system() {
+            true => System.alloc(layout),
+            false => MiMalloc.alloc(layout),
+        }
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match self.system() {
+            true => System.alloc_zeroed(layout),
+            false => MiMalloc.alloc_zeroed(layout),
+        }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.system() {
+            true => System.dealloc(ptr, layout),
+            false => MiMalloc.dealloc(ptr, layout),
+        }
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match self.system() {
+            true => System.realloc(ptr, layout, new_size),
+            false => MiMalloc.realloc(ptr, layout, new_size),
+        }
+    }
+}
@@ -1,24 +1,47 @@
 use core::str;
 use std::{
     borrow::Cow,
+    cell::Cell,
     fs::File,
-    io::{Read, Write},
+    io::{BufRead, BufReader, IsTerminal, Read, Write},
     ops::Not,
     path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
 };
 
 use anyhow::Context;
 use tar::Archive;
 
+const PACKAGE_PAYLOAD: &str = "package.tgz";
+const PACKAGE_TAR: &str = "package.tar";
+
 pub struct Asset {
     tmp_path: PathBuf,
     filename: String,
     // exist package path
     package: Option<PathBuf>,
+    // hide progress output
+    quiet: bool,
+    // proxy of the package download
+    proxy: Option<String>,
+    // longest wait for a single read of the package, zero waits forever
+    read_timeout: Duration,
+    // set once a read of the package timed out
+    timed_out: Arc<AtomicBool>,
 }
 
 impl Asset {
-    pub fn new(package: Option<PathBuf>) -> anyhow::Result<Self> {
+    pub fn new(
+        package: Option<PathBuf>,
+        quiet: bool,
+        proxy: Option<String>,
+        read_timeout: Duration,
+    ) -> anyhow::Result<Self> {
         let tmp_path = PathBuf::from("/tmp/xunlei_bin");
         tmp_path
             .exists()
@@ -28,33 +51,51 @@ impl Asset {
             tmp_path,
             filename: format!("nasxunlei-DSM7-{}.spk", crate::constant::SUPPORT_ARCH),
             package,
+            quiet,
+            proxy,
+            read_timeout,
+            timed_out: Arc::new(AtomicBool::new(false)),
         })
     }
 
-    pub fn init(&self) -> anyhow::Result<()> {
+    /// Path of the package, downloading it first when none is given
+    pub fn fetch(&self) -> anyhow::Result<PathBuf> {
         match self.package {
             Some(ref filepath) => {
-                // check filepath is exists
-                if !filepath.exists() {
-                    anyhow::bail!("package path: {} not found", filepath.display());
-                }
-
-                // check filepath is a file
-                let metadata = std::fs::metadata(filepath)?;
-                if !metadata.is_file() {
-                    anyhow::bail!("package path: {} must be a file", filepath.display());
+                // A stalled network filesystem can hang on stat too
+                let path = filepath.clone();
+                let metadata = self.timed(move || std::fs::metadata(path))?;
+                match metadata {
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        anyhow::bail!("package path: {} not found", filepath.display())
+                    }
+                    Err(err) => return Err(err.into()),
+                    // check filepath is a file
+                    Ok(metadata) if !metadata.is_file() => {
+                        anyhow::bail!("package path: {} must be a file", filepath.display())
+                    }
+                    Ok(_) => {}
                 }
 
-                Self::decompressor(&self.tmp_path, filepath)?;
-                Ok(())
+                Ok(filepath.clone())
             }
             None => {
-                let response =
-                    ureq::get(&format!("http://down.sandai.net/nas/{}", self.filename)).call()?;
+                let url = format!("http://down.sandai.net/nas/{}", self.filename);
+                let (agent, proxy) = crate::util::http_agent(&url, self.proxy.as_deref())?;
+                let response = agent.get(&url).call().with_context(|| match proxy {
+                    Some(ref proxy) => format!(
+                        "Failed to download {url} through {}",
+                        crate::util::redact_proxy(proxy)
+                    ),
+                    None => format!("Failed to download {url}"),
+                })?;
 
                 let total_size = response.header("Content-Length").unwrap().parse::<u64>()?;
 
-                let pb = indicatif::ProgressBar::new(total_size);
+                let pb = match self.quiet {
+                    true => indicatif::ProgressBar::hidden(),
+                    false => indicatif::ProgressBar::new(total_size),
+                };
                 pb.set_style(indicatif::ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")?
                 .progress_chars("#>-"));
 
@@ -79,60 +120,132 @@ impl Asset {
                     pb.set_position(new);
                 }
                 pb.finish_with_message("downloaded");
-                println!();
+                if !self.quiet {
+                    println!();
+                }
 
                 output_file.flush()?;
                 drop(output_file);
 
-                Self::decompressor(&self.tmp_path, &filepath)
-                    .context("There was an error extracting the download package")
+                Ok(filepath)
             }
         }
     }
 
-    fn decompressor<T: AsRef<Path>>(dir: T, archive_path: T) -> anyhow::Result<()> {
-        const PACKAGE_XZ: &str = "package.tgz";
-        const PACKAGE_TAR: &str = "package.tar";
+    /// Extract the thunder files of the package
+    pub fn extract(&self, filepath: &Path) -> anyhow::Result<()> {
+        match self.package {
+            Some(_) => self.decompressor(self.tmp_path.as_path(), filepath),
+            None => self
+                .decompressor(self.tmp_path.as_path(), filepath)
+                .context("There was an error extracting the download package"),
+        }
+    }
+
+    /// Remove the downloaded and extracted files
+    pub fn clean(&self) {
+        let _ = std::fs::remove_dir_all(&self.tmp_path);
+    }
 
-        let xz_path = PathBuf::from(dir.as_ref()).join(PACKAGE_XZ);
+    fn decompressor<T: AsRef<Path>>(&self, dir: T, archive_path: T) -> anyhow::Result<()> {
+        let payload_path = PathBuf::from(dir.as_ref()).join(PACKAGE_PAYLOAD);
         let tar_path = PathBuf::from(dir.as_ref()).join(PACKAGE_TAR);
 
-        // read archive file
-        let archive_file = File::open(&archive_path).context(format!(
-            "file {} not found",
-            archive_path.as_ref().display()
-        ))?;
+        let result = self.unpack(
+            dir.as_ref(),
+            archive_path.as_ref(),
+            &payload_path,
+            &tar_path,
+        );
+        let result = match self.timed_out.load(Ordering::SeqCst) {
+            true => Err(anyhow::anyhow!(
+                "Package read timed out after {}s: {}",
+                self.read_timeout.as_secs(),
+                archive_path.as_ref().display()
+            )),
+            false => result,
+        };
+        if let Err(ref err) = result {
+            // Remove everything extracted so far
+            let _ = std::fs::remove_file(&payload_path);
+            let _ = std::fs::remove_file(&tar_path);
+            // A corrupt download must not be reused
+            if err.is::<Corrupt>() && self.package.is_none() {
+                let _ = std::fs::remove_file(archive_path.as_ref());
+            }
+        }
+        result
+    }
 
-        let mut archive = Archive::new(archive_file);
-        let mut xz_file = std::fs::File::create(&xz_path)?;
+    fn unpack(
+        &self,
+        dir: &Path,
+        archive_path: &Path,
+        payload_path: &Path,
+        tar_path: &Path,
+    ) -> anyhow::Result<()> {
+        // read archive file, which may be on a network filesystem
+        let (archive_file, total) =
+            TimedReader::open(archive_path, self.read_timeout, self.timed_out.clone())?;
+        let offset = Offset::default();
+        let corrupt = |reason: String| offset.corrupt(archive_path, reason);
+        let mut archive = Archive::new(Compression::decoder(self.progress(
+            "Reading package",
+            offset.reader(archive_file),
+            total,
+        ))?);
+        let mut payload_file = std::fs::File::create(payload_path)?;
 
-        for file in archive.entries()? {
+        let mut found = false;
+        for file in archive.entries().map_err(|err| corrupt(err.to_string()))? {
             // Make sure there wasn't an I/O error
-            let file = file?;
-            if format!("{}", file.path()?.display()).contains(PACKAGE_XZ) {
-                Self::copy_write(file, &mut xz_file)?;
+            let file = file.map_err(|err| corrupt(err.to_string()))?;
+            if format!("{}", file.path()?.display()).contains(PACKAGE_PAYLOAD) {
+                let size = file.size();
+                let written = Self::copy_write(file, &mut payload_file)
+                    .map_err(|err| err.into_error(corrupt))?;
+                if written != size {
+                    return Err(corrupt(format!(
+                        "{PACKAGE_PAYLOAD} has {written} of {size} bytes"
+                    )));
+                }
+                found = true;
                 break;
             }
         }
+        if !found {
+            return Err(corrupt(format!("no {PACKAGE_PAYLOAD} found")));
+        }
 
-        xz_file.flush()?;
-        drop(xz_file);
+        payload_file.flush()?;
+        drop(payload_file);
 
-        // read xz compressed file
-        let xz_file = std::fs::File::open(&xz_path)?;
-        let decompressor = xz::read::XzDecoder::new(xz_file);
+        // read compressed payload file
+        let payload_file = std::fs::File::open(payload_path)?;
+        let total = payload_file.metadata()?.len();
+        let offset = Offset::default();
+        let corrupt = |reason: String| offset.corrupt(payload_path, reason);
+        let decompressor = Compression::decoder(self.progress(
+            "Decompressing package",
+            offset.reader(payload_file),
+            total,
+        ))?;
 
-        let mut tar_file = std::fs::File::create(&tar_path)?;
-        Self::copy_write(decompressor, &mut tar_file)?;
+        let mut tar_file = std::fs::File::create(tar_path)?;
+        Self::copy_write(decompressor, &mut tar_file).map_err(|err| err.into_error(corrupt))?;
         tar_file.flush()?;
         drop(tar_file);
 
-        // remove xz compressed file
-        std::fs::remove_file(&xz_path)?;
+        // remove compressed payload file
+        std::fs::remove_file(payload_path)?;
+
+        // Check the whole archive before writing any file
+        verify_tar(tar_path)?;
 
         // read tar file
-        let tar_file = std::fs::File::open(&tar_path)?;
-        let mut archive = Archive::new(tar_file);
+        let tar_file = std::fs::File::open(tar_path)?;
+        let total = tar_file.metadata()?.len();
+        let mut archive = Archive::new(self.progress("Extracting package", tar_file, total));
 
         for file in archive.entries()? {
             let file = file?;
@@ -143,12 +256,11 @@ impl Asset {
                 || path.contains("bin/bin/xunlei-pan-cli")
             {
                 let filename = path.trim_start_matches("bin/bin/");
-                let filepath = PathBuf::from(dir.as_ref()).join(filename);
+                let filepath = dir.join(filename);
                 let mut target = File::create(filepath)?;
                 Self::copy_write(file, &mut target)?;
             } else if path.contains("ui/index.cgi") {
-                let mut target =
-                    File::create(PathBuf::from(dir.as_ref()).join("xunlei-pan-cli-web"))?;
+                let mut target = File::create(dir.join("xunlei-pan-cli-web"))?;
                 Self::copy_write(file, &mut target)?;
             }
         }
@@ -158,16 +270,62 @@ impl Asset {
         Ok(())
     }
 
-    fn copy_write(mut src: impl Read, dest: &mut File) -> anyhow::Result<()> {
+    /// Run `f` on a thread, failing when it does not return within the read timeout
+    fn timed<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        if self.read_timeout.is_zero() {
+            return Ok(f());
+        }
+        let (tx, rx) = mpsc::sync_channel(1);
+        std::thread::spawn(move || {
+            let _ = tx.send(f());
+        });
+        rx.recv_timeout(self.read_timeout).map_err(|_| {
+            self.timed_out.store(true, Ordering::SeqCst);
+            anyhow::anyhow!(
+                "Package read timed out after {}s",
+                self.read_timeout.as_secs()
+            )
+        })
+    }
+
+    fn progress<R: Read>(&self, label: &'static str, inner: R, total: u64) -> ProgressReader<R> {
+        let bar = (!self.quiet && std::io::stdout().is_terminal()).then(|| {
+            let pb = indicatif::ProgressBar::new(total);
+            if let Ok(style) = indicatif::ProgressStyle::with_template(
+                "{msg} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({percent}%)",
+            ) {
+                pb.set_style(style.progress_chars("#>-"));
+            }
+            pb.set_message(label);
+            pb
+        });
+        ProgressReader {
+            inner,
+            label,
+            total,
+            read: 0,
+            reported: 0,
+            quiet: self.quiet,
+            bar,
+        }
+    }
+
+    fn copy_write(mut src: impl Read, dest: &mut File) -> Result<u64, CopyError> {
         let mut buffer = [0; 1024];
+        let mut written = 0;
 
         loop {
-            match src.read(&mut buffer)? {
-                0 => break,
-                n => dest.write_all(&buffer[..n])?,
-            };
+            let n = src.read(&mut buffer).map_err(CopyError::Read)?;
+            if n == 0 {
+                break;
+            }
+            dest.write_all(&buffer[..n]).map_err(CopyError::Write)?;
+            written += n as u64;
         }
-        Ok(())
+        Ok(written)
     }
 }
 
@@ -178,7 +336,7 @@ impl Asset {
         )?)
     }
 
-    pub fn get(&self, filename: &str) -> anyhow::Result<Cow<[u8]>> {
+    pub fn get(&self, filename: &str) -> anyhow::Result<Cow<'_, [u8]>> {
         let vec = std::fs::read(PathBuf::from(&self.tmp_path).join(filename))?;
         Ok(std::borrow::Cow::from(vec))
     }
@@ -186,14 +344,337 @@ impl Asset {
     pub fn iter(&self) -> anyhow::Result<Vec<String>> {
         let entries = std::fs::read_dir(&self.tmp_path)?;
         let mut file_names = Vec::new();
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(file_name) = path.file_name() {
-                    file_names.push(file_name.to_string_lossy().to_string());
-                }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(file_name) = path.file_name() {
+                file_names.push(file_name.to_string_lossy().to_string());
             }
         }
         Ok(file_names)
     }
 }
+
+/// A package that ended early or failed its integrity checks
+#[derive(Debug)]
+struct Corrupt {
+    file: PathBuf,
+    position: u64,
+    reason: String,
+}
+
+impl std::fmt::Display for Corrupt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Package appears truncated or corrupt: {} at byte {}: {}",
+            self.file.display(),
+            self.position,
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for Corrupt {}
+
+/// Error of [`Asset::copy_write`], reading means the package is corrupt
+enum CopyError {
+    Read(std::io::Error),
+    Write(std::io::Error),
+}
+
+impl CopyError {
+    fn into_error(self, corrupt: impl Fn(String) -> anyhow::Error) -> anyhow::Error {
+        match self {
+            CopyError::Read(err) => corrupt(err.to_string()),
+            CopyError::Write(err) => err.into(),
+        }
+    }
+}
+
+impl From<CopyError> for anyhow::Error {
+    fn from(err: CopyError) -> Self {
+        match err {
+            CopyError::Read(err) | CopyError::Write(err) => err.into(),
+        }
+    }
+}
+
+/// Number of bytes read from a file, shared with the reader wrapping it
+#[derive(Default)]
+struct Offset(Rc<Cell<u64>>);
+
+impl Offset {
+    fn reader<R: Read>(&self, inner: R) -> OffsetReader<R> {
+        OffsetReader(inner, self.0.clone())
+    }
+
+    fn corrupt(&self, file: &Path, reason: String) -> anyhow::Error {
+        Corrupt {
+            file: file.to_owned(),
+            position: self.0.get(),
+            reason,
+        }
+        .into()
+    }
+}
+
+struct OffsetReader<R>(R, Rc<Cell<u64>>);
+
+impl<R: Read> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.0.read(buf)?;
+        self.1.set(self.1.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Reader of a file on its own thread, so that a read stalled by the filesystem fails
+/// after the timeout instead of hanging; the stalled thread is abandoned
+struct TimedReader {
+    chunks: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    timeout: Duration,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl TimedReader {
+    /// Size of a read on the thread
+    const CHUNK: usize = 64 << 10;
+
+    /// Open `path` and answer the reader with the file size, a zero timeout waits forever
+    fn open(
+        path: &Path,
+        timeout: Duration,
+        timed_out: Arc<AtomicBool>,
+    ) -> anyhow::Result<(Self, u64)> {
+        let (len_tx, len_rx) = mpsc::sync_channel(1);
+        // A few chunks ahead of the decoder
+        let (tx, chunks) = mpsc::sync_channel(4);
+        let file_path = path.to_owned();
+        std::thread::Builder::new()
+            .name("package".to_owned())
+            .spawn(move || {
+                let mut file = match File::open(&file_path)
+                    .and_then(|file| file.metadata().map(|metadata| (file, metadata.len())))
+                {
+                    Ok((file, len)) => {
+                        let _ = len_tx.send(Ok(len));
+                        file
+                    }
+                    Err(err) => {
+                        let _ = len_tx.send(Err(err));
+                        return;
+                    }
+                };
+                loop {
+                    let mut chunk = vec![0; Self::CHUNK];
+                    let result = file.read(&mut chunk).map(|n| {
+                        chunk.truncate(n);
+                        chunk
+                    });
+                    let end = result.as_ref().map_or(true, Vec::is_empty);
+                    if tx.send(result).is_err() || end {
+                        return;
+                    }
+                }
+            })?;
+        let len = match timeout.is_zero() {
+            true => len_rx
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            false => len_rx.recv_timeout(timeout),
+        };
+        let len = match len {
+            Ok(len) => len.context(format!("file {} not found", path.display()))?,
+            Err(_) => {
+                timed_out.store(true, Ordering::SeqCst);
+                anyhow::bail!(
+                    "Package read timed out after {}s: {}",
+                    timeout.as_secs(),
+                    path.display()
+                )
+            }
+        };
+        let reader = Self {
+            chunks,
+            chunk: Vec::new(),
+            pos: 0,
+            timeout,
+            timed_out,
+        };
+        Ok((reader, len))
+    }
+}
+
+impl Read for TimedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            let chunk = match self.timeout.is_zero() {
+                true => self
+                    .chunks
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                false => self.chunks.recv_timeout(self.timeout),
+            };
+            match chunk {
+                Ok(chunk) => self.chunk = chunk?,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    self.timed_out.store(true, Ordering::SeqCst);
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "package read timed out",
+                    ));
+                }
+            }
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Read every entry of the tar archive and check it ends with an end-of-archive block
+fn verify_tar(tar_path: &Path) -> anyhow::Result<()> {
+    let corrupt = |position: u64, reason: String| -> anyhow::Error {
+        Corrupt {
+            file: tar_path.to_owned(),
+            position,
+            reason,
+        }
+        .into()
+    };
+
+    let file = File::open(tar_path)?;
+    let len = file.metadata()?.len();
+    let mut archive = Archive::new(BufReader::new(file));
+    let mut end = 0;
+    for entry in archive
+        .entries()
+        .map_err(|err| corrupt(end, err.to_string()))?
+    {
+        let mut entry = entry.map_err(|err| corrupt(end, err.to_string()))?;
+        let (start, size) = (entry.raw_file_position(), entry.size());
+        let read = std::io::copy(&mut entry, &mut std::io::sink())
+            .map_err(|err| corrupt(start, err.to_string()))?;
+        if read != size {
+            return Err(corrupt(
+                start + read,
+                format!("{} has {read} of {size} bytes", entry.path()?.display()),
+            ));
+        }
+        end = start + size.div_ceil(512) * 512;
+    }
+    if len < end + 512 {
+        return Err(corrupt(len, String::from("missing end-of-archive block")));
+    }
+    Ok(())
+}
+
+/// Package compression, detected by magic bytes
+#[derive(Debug, Clone, Copy)]
+enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+    // Uncompressed tar archive
+    Tar,
+}
+
+impl Compression {
+    const MAGIC_LEN: usize = 8;
+
+    fn detect(head: &[u8]) -> anyhow::Result<Self> {
+        match head {
+            [0x1f, 0x8b, ..] => Ok(Self::Gzip),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Ok(Self::Xz),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Ok(Self::Zstd),
+            [b'B', b'Z', b'h', ..] => Ok(Self::Bzip2),
+            _ if Self::is_tar(head) => Ok(Self::Tar),
+            _ => {
+                let magic = head
+                    .iter()
+                    .take(Self::MAGIC_LEN)
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                anyhow::bail!("Unsupported package format, magic bytes: [{magic}]")
+            }
+        }
+    }
+
+    /// A tar archive has no magic prefix, so validate the first header checksum
+    fn is_tar(head: &[u8]) -> bool {
+        if head.len() < 512 {
+            return false;
+        }
+        let header = tar::Header::from_byte_slice(&head[..512]);
+        let sum = head[..512]
+            .iter()
+            .enumerate()
+            .map(|(i, b)| match i {
+                148..=155 => u32::from(b' '),
+                _ => u32::from(*b),
+            })
+            .sum::<u32>();
+        header.cksum().is_ok_and(|cksum| cksum == sum)
+    }
+
+    /// Wrap the reader with the decoder matching its magic bytes
+    fn decoder<'a, R: Read + 'a>(reader: R) -> anyhow::Result<Box<dyn Read + 'a>> {
+        let mut reader = BufReader::new(reader);
+        let compression = Self::detect(reader.fill_buf()?)?;
+        log::debug!("Package compression: {:?}", compression);
+        Ok(match compression {
+            Self::Gzip => Box::new(libflate::gzip::MultiDecoder::new(reader)?),
+            Self::Xz => Box::new(xz::read::XzDecoder::new_multi_decoder(reader)),
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+            Self::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            Self::Tar => Box::new(reader),
+        })
+    }
+}
+
+/// Reader that reports how many bytes of the package have been processed,
+/// drawn as a bar on a TTY and as a line every 10% otherwise
+struct ProgressReader<R> {
+    inner: R,
+    label: &'static str,
+    total: u64,
+    read: u64,
+    // last reported percentage
+    reported: u64,
+    quiet: bool,
+    bar: Option<indicatif::ProgressBar>,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        match self.bar {
+            Some(ref pb) => {
+                pb.set_position(self.read);
+                if n == 0 {
+                    pb.finish();
+                }
+            }
+            None if !self.quiet && self.total > 0 => {
+                let percent = std::cmp::min(self.read * 100 / self.total, 100);
+                if percent >= self.reported + 10 || (n == 0 && percent > self.reported) {
+                    self.reported = percent - percent % 10;
+                    println!(
+                        "{}: {}% ({}/{} bytes)",
+                        self.label, percent, self.read, self.total
+                    );
+                }
+            }
+            None => {}
+        }
+        Ok(n)
+    }
+}
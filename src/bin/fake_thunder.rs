@@ -0,0 +1,107 @@
+//! Stand-in for the thunder launcher and web CGI, used to exercise the serve and
+//! daemon lifecycle without a real thunder package.
+//!
+//! As a launcher it accepts the same `-launcher_listen`, `-pid` and `-logfile`
+//! arguments, writes its pid, answers every request on the launcher socket with an
+//! empty JSON object and exits cleanly on SIGINT/SIGTERM/SIGHUP. When started as a
+//! CGI program (`GATEWAY_INTERFACE` is set) it echoes the request method and path, after
+//! sleeping for `?sleep=<seconds>` if given.
+//!
+//! ```shell
+//! cargo build --features test-utils
+//! thunder run --thunder-bin target/debug/fake-thunder --thunder-web-bin target/debug/fake-thunder
+//! ```
+//!
+//! The tests under `tests/` run the server against it.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::UnixListener,
+    path::PathBuf,
+};
+
+fn main() -> anyhow::Result<()> {
+    if std::env::var_os("GATEWAY_INTERFACE").is_some() {
+        return cgi();
+    }
+
+    let mut listen = None;
+    let mut pid_file = None;
+    let mut log_file = None;
+    for arg in std::env::args().skip(1) {
+        if let Some(v) = arg.strip_prefix("-launcher_listen=") {
+            listen = Some(PathBuf::from(v.trim_start_matches("unix://")));
+        } else if let Some(v) = arg.strip_prefix("-pid=") {
+            pid_file = Some(PathBuf::from(v));
+        } else if let Some(v) = arg.strip_prefix("-logfile=") {
+            log_file = Some(PathBuf::from(v));
+        }
+    }
+
+    if let Some(ref pid_file) = pid_file {
+        std::fs::write(pid_file, std::process::id().to_string())?;
+    }
+    if let Some(ref log_file) = log_file {
+        std::fs::write(log_file, "fake thunder started\n")?;
+    }
+
+    if let Some(ref listen) = listen {
+        let _ = std::fs::remove_file(listen);
+        let listener = UnixListener::bind(listen)?;
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                // Read the request head and reply with an empty object
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                    line.clear();
+                }
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}",
+                );
+            }
+        });
+    }
+
+    let mut signals = signal_hook::iterator::Signals::new([
+        signal_hook::consts::SIGINT,
+        signal_hook::consts::SIGHUP,
+        signal_hook::consts::SIGTERM,
+    ])?;
+    let _ = signals.forever().next();
+
+    for path in [listen, pid_file].into_iter().flatten() {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+/// Answer a single CGI request
+fn cgi() -> anyhow::Result<()> {
+    let method = std::env::var("REQUEST_METHOD").unwrap_or_default();
+    let path = std::env::var("PATH_INFO").unwrap_or_default();
+    let length = std::env::var("CONTENT_LENGTH")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or_default();
+
+    let mut body = Vec::new();
+    std::io::stdin().take(length).read_to_end(&mut body)?;
+
+    // `?sleep=<seconds>` simulates a stuck upstream
+    let query = std::env::var("QUERY_STRING").unwrap_or_default();
+    if let Some(secs) = query
+        .split('&')
+        .find_map(|kv| kv.strip_prefix("sleep="))
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        std::thread::sleep(std::time::Duration::from_secs(secs));
+    }
+
+    let mut stdout = std::io::stdout();
+    write!(stdout, "Status: 200 OK\r\n")?;
+    write!(stdout, "Content-Type: text/plain\r\n\r\n")?;
+    write!(stdout, "{method} {path} {}", body.len())?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,353 @@
+use clap::{parser::ValueSource, ArgMatches, Args, FromArgMatches};
+use serde::Serialize;
+use std::{
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
+
+use crate::{InstallConfig, ServeConfig};
+
+/// Options whose values are never printed
+const REDACTED: [&str; 4] = [
+    "auth_password",
+    "viewer_password",
+    "session_key",
+    "tls_pkcs12_password",
+];
+
+/// A resolved configuration value and where it comes from
+#[derive(Serialize)]
+struct Entry {
+    name: String,
+    value: Option<String>,
+    source: &'static str,
+}
+
+#[derive(Serialize)]
+struct Resolved {
+    serve_file: String,
+    serve: Vec<Entry>,
+    install_file: String,
+    install_drop_ins: Vec<String>,
+    install: Vec<Entry>,
+}
+
+/// Print the resolved serve and install configuration, `matches` are those of the
+/// subcommand the serve options were parsed from
+pub(crate) fn show(matches: &ArgMatches, json: bool) -> anyhow::Result<()> {
+    let mut serve = Vec::new();
+    let (resolved, _) = merge("show", matches, &read_stored().unwrap_or_default())?;
+    let command = ServeConfig::augment_args(clap::Command::new("serve"));
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let value = resolved
+            .get_raw(id)
+            .map(|v| {
+                v.map(|v| v.to_string_lossy().into_owned())
+                    .map(|v| match (id, v.split_once('=')) {
+                        ("thunder_env", Some((key, value))) => {
+                            format!("{key}={}", crate::serve::child_env::redact(key, value))
+                        }
+                        _ => v,
+                    })
+                    .collect::<Vec<String>>()
+                    .join(",")
+            })
+            .map(|v| match REDACTED.contains(&id) {
+                true => String::from("<redacted>"),
+                false => v,
+            });
+        let source = match (matches.value_source(id), resolved.value_source(id)) {
+            (Some(ValueSource::CommandLine), _) => "flag",
+            (Some(ValueSource::EnvVariable), _) => "env",
+            (_, Some(ValueSource::CommandLine)) => "file",
+            _ => "default",
+        };
+        serve.push(Entry {
+            name: id.to_owned(),
+            value,
+            source,
+        });
+    }
+
+    let mut install = Vec::new();
+    if let Ok(config) = InstallConfig::read_from_file() {
+        for (name, value) in [
+            ("uid", config.uid.to_string()),
+            ("gid", config.gid.to_string()),
+            ("config_path", config.config_path.display().to_string()),
+            ("download_path", config.download_path.display().to_string()),
+            (
+                "mount_bind_download_path",
+                config.mount_bind_download_path.display().to_string(),
+            ),
+            ("allow_nested_paths", config.allow_nested_paths.to_string()),
+        ] {
+            install.push(Entry {
+                name: name.to_owned(),
+                value: Some(value),
+                source: "file",
+            });
+        }
+    }
+
+    let resolved = Resolved {
+        serve_file: ServeConfig::path().display().to_string(),
+        serve,
+        install_file: InstallConfig::path().display().to_string(),
+        install_drop_ins: InstallConfig::drop_ins()
+            .unwrap_or_default()
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+        install,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&resolved)?);
+        return Ok(());
+    }
+
+    println!("[serve] {}", resolved.serve_file);
+    for entry in resolved.serve.iter() {
+        print_entry(entry);
+    }
+    println!();
+    println!("[install] {}", resolved.install_file);
+    for drop_in in resolved.install_drop_ins.iter() {
+        println!("# merged with {drop_in}");
+    }
+    if resolved.install.is_empty() {
+        println!("# not installed");
+    }
+    for entry in resolved.install.iter() {
+        print_entry(entry);
+    }
+    Ok(())
+}
+
+fn print_entry(entry: &Entry) {
+    match entry.value {
+        Some(ref value) => println!("{} = {} ({})", entry.name, value, entry.source),
+        None => println!("# {} is not set", entry.name),
+    }
+}
+
+/// Keys read from the install config file
+const INSTALL_KEYS: [&str; 6] = [
+    "uid",
+    "gid",
+    "config_path",
+    "download_path",
+    "mount_bind_download_path",
+    "allow_nested_paths",
+];
+
+/// Print the install config keys and the serve options with their help and default
+/// values. Options without a default are commented out.
+pub(crate) fn dump_defaults() {
+    let install = InstallConfig::augment_args(clap::Command::new("install"));
+    println!("# Install config: {}", InstallConfig::path().display());
+    for arg in install.get_arguments() {
+        if INSTALL_KEYS.contains(&arg.get_id().as_str()) {
+            print_default(arg);
+        }
+    }
+    println!();
+    let serve = ServeConfig::augment_args(clap::Command::new("serve"));
+    println!("# Serve options: {}", ServeConfig::path().display());
+    println!("# Booleans are enabled with true, lists repeat the key once per value");
+    for arg in serve.get_arguments() {
+        if !arg.is_hide_set() && arg.get_long().is_some() {
+            print_default(arg);
+        }
+    }
+}
+
+fn print_default(arg: &clap::Arg) {
+    println!();
+    if let Some(help) = arg.get_help() {
+        println!("# {help}");
+    }
+    if let Some(env) = arg.get_env() {
+        println!("# env: {}", env.to_string_lossy());
+    }
+    let id = arg.get_id().as_str();
+    let defaults = arg.get_default_values();
+    match (arg.get_action().takes_values(), defaults.is_empty()) {
+        (false, _) => println!("#{id}=true"),
+        (true, true) => println!("#{id}="),
+        (true, false) => {
+            for value in defaults {
+                println!("{id}={}", value.to_string_lossy());
+            }
+        }
+    }
+}
+
+/// Merge the stored serve options with the given ones. Options passed on the command line
+/// win over environment variables, which win over stored options. Returns the merged
+/// matches and the options that were passed on the command line or stored.
+fn merge(
+    name: &'static str,
+    matches: &ArgMatches,
+    stored: &[(String, String)],
+) -> anyhow::Result<(ArgMatches, Vec<(String, String)>)> {
+    let command = ServeConfig::augment_args(clap::Command::new(name));
+
+    let mut argv = vec![name.to_owned()];
+    let mut merged = Vec::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let values = match matches.value_source(id) {
+            Some(ValueSource::CommandLine) => matches
+                .get_raw(id)
+                .map(|v| v.map(|v| v.to_string_lossy().into_owned()).collect())
+                .unwrap_or_default(),
+            _ => stored
+                .iter()
+                .filter(|(k, _)| k == id)
+                .map(|(_, v)| v.clone())
+                .collect::<Vec<String>>(),
+        };
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        for value in values {
+            let from_env = matches.value_source(id) == Some(ValueSource::EnvVariable);
+            if !from_env {
+                match arg.get_action().takes_values() {
+                    true => argv.push(format!("--{long}={value}")),
+                    false if value == "true" => argv.push(format!("--{long}")),
+                    false => {}
+                }
+            }
+            merged.push((id.to_owned(), value));
+        }
+    }
+
+    Ok((command.try_get_matches_from(argv)?, merged))
+}
+
+/// Resolve the serve options of `run` or `start` and store them for the next invocation
+pub(crate) fn persist_serve(
+    name: &'static str,
+    matches: &ArgMatches,
+) -> anyhow::Result<ServeConfig> {
+    let (resolved, merged) = merge(name, matches, &read_stored().unwrap_or_default())?;
+    write_stored(&merged)?;
+    Ok(ServeConfig::from_arg_matches(&resolved)?)
+}
+
+/// Serve options of the command line merged over the stored ones, without storing them
+pub(crate) fn resolve_serve(
+    name: &'static str,
+    matches: &ArgMatches,
+) -> anyhow::Result<ServeConfig> {
+    let (resolved, _) = merge(name, matches, &read_stored().unwrap_or_default())?;
+    Ok(ServeConfig::from_arg_matches(&resolved)?)
+}
+
+/// Replace the stored authentication password and session key
+pub(crate) fn store_auth(password: &str, session_key: &str) -> anyhow::Result<()> {
+    let mut stored = read_stored().unwrap_or_default();
+    stored.retain(|(id, _)| id != "auth_password" && id != "session_key");
+    stored.push(("auth_password".to_owned(), password.to_owned()));
+    stored.push(("session_key".to_owned(), session_key.to_owned()));
+    write_stored(&stored)
+}
+
+/// Environment variable of a serve option
+pub(crate) fn env_name(id: &str) -> Option<String> {
+    ServeConfig::augment_args(clap::Command::new("serve"))
+        .get_arguments()
+        .find(|arg| arg.get_id() == id)
+        .and_then(|arg| arg.get_env())
+        .map(|env| env.to_string_lossy().into_owned())
+}
+
+/// Last stored value of a serve option
+pub(crate) fn stored(id: &str) -> Option<String> {
+    read_stored()
+        .ok()?
+        .into_iter()
+        .rev()
+        .find(|(k, _)| k == id)
+        .map(|(_, v)| v)
+}
+
+/// Write the serve options, readable by root only
+fn write_stored(values: &[(String, String)]) -> anyhow::Result<()> {
+    let path = ServeConfig::path();
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|err| crate::util::write_error(&path, err))?;
+    // The file may predate the mode above
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    for (id, value) in values {
+        writeln!(file, "{id}={value}")?;
+    }
+    Ok(())
+}
+
+/// Stored serve options, in file order
+fn read_stored() -> anyhow::Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(ServeConfig::path())?;
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(args: &[&str]) -> ArgMatches {
+        ServeConfig::augment_args(clap::Command::new("run"))
+            .try_get_matches_from(std::iter::once("run").chain(args.iter().copied()))
+            .unwrap()
+    }
+
+    fn stored(values: &[(&str, &str)]) -> Vec<(String, String)> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn stored_switch_is_kept() {
+        let (resolved, merged) = merge("run", &matches(&[]), &stored(&[("hsts", "true")])).unwrap();
+        assert!(ServeConfig::from_arg_matches(&resolved).unwrap().hsts);
+        assert_eq!(merged, stored(&[("hsts", "true")]));
+    }
+
+    #[test]
+    fn stored_switch_is_cleared() {
+        let (resolved, merged) = merge(
+            "run",
+            &matches(&["--hsts=false"]),
+            &stored(&[("hsts", "true")]),
+        )
+        .unwrap();
+        assert!(!ServeConfig::from_arg_matches(&resolved).unwrap().hsts);
+        assert_eq!(merged, stored(&[("hsts", "false")]));
+
+        // The cleared value is stored in turn
+        let (resolved, _) = merge("run", &matches(&[]), &merged).unwrap();
+        assert!(!ServeConfig::from_arg_matches(&resolved).unwrap().hsts);
+    }
+
+    #[test]
+    fn bare_switch_is_set() {
+        let (resolved, merged) = merge("run", &matches(&["--hsts"]), &[]).unwrap();
+        assert!(ServeConfig::from_arg_matches(&resolved).unwrap().hsts);
+        assert_eq!(merged, stored(&[("hsts", "true")]));
+    }
+}
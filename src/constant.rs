@@ -34,6 +34,7 @@ pub const LAUNCH_PID_FILE: &str =
     "/var/packages/pan-xunlei-com/target/var/pan-xunlei-com-launcher.pid";
 pub const LAUNCH_LOG_FILE: &str =
     "/var/packages/pan-xunlei-com/target/var/pan-xunlei-com-launcher.log";
+pub const SYNOPKG_VERSION: &str = "/var/packages/pan-xunlei-com/target/version";
 pub const INST_LOG: &str = "/var/packages/pan-xunlei-com/target/var/pan-xunlei-com_install.log";
 
 /// Web UI request path
@@ -43,3 +44,6 @@ pub const SYNOPKG_WEB_UI_HOME: &str = "/webman/3rdparty/pan-xunlei-com/index.cgi
 pub const DEFAULT_DOWNLOAD_PATH: &str = "/opt/thunder/downloads";
 pub const DEFAULT_BIND_DOWNLOAD_PATH: &str = "/thunder";
 pub const DEFAULT_CONFIG_PATH: &str = "/opt/thunder";
+pub const DEFAULT_INSTALL_CONFIG: &str = "/etc/.thunder";
+pub const DEFAULT_CONFIG_DIR: &str = "/etc/thunder.d";
+pub const DEFAULT_PID_PATH: &str = "/var/run/thunder.pid";
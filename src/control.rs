@@ -0,0 +1,118 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        net::{UnixListener, UnixStream},
+        prelude::PermissionsExt,
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+
+/// Control socket file name under the thunder config directory
+const SOCK_NAME: &str = "thunder.sock";
+
+/// Control socket path
+pub fn sock_path(config_path: &Path) -> PathBuf {
+    config_path.join(SOCK_NAME)
+}
+
+/// Control socket commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Gracefully stop the running server
+    Shutdown,
+    /// Pause all thunder processes
+    Pause,
+    /// Resume all thunder processes
+    Resume,
+    /// Serve the maintenance page
+    MaintenanceOn,
+    /// Resume serving the thunder UI
+    MaintenanceOff,
+    /// Apply the stored authentication password and session key
+    ReloadAuth,
+}
+
+impl Command {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Command::Shutdown => "shutdown",
+            Command::Pause => "pause",
+            Command::Resume => "resume",
+            Command::MaintenanceOn => "maintenance on",
+            Command::MaintenanceOff => "maintenance off",
+            Command::ReloadAuth => "reload auth",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "shutdown" => Some(Command::Shutdown),
+            "pause" => Some(Command::Pause),
+            "resume" => Some(Command::Resume),
+            "maintenance on" => Some(Command::MaintenanceOn),
+            "maintenance off" => Some(Command::MaintenanceOff),
+            "reload auth" => Some(Command::ReloadAuth),
+            _ => None,
+        }
+    }
+}
+
+/// Listen on the control socket, handling each command on a background thread
+pub fn listen<F>(path: &Path, handler: F) -> anyhow::Result<()>
+where
+    F: Fn(Command) -> anyhow::Result<String> + Send + 'static,
+{
+    // Remove the socket left by a previous run
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)
+        .context(format!("Failed to bind control socket: {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    log::info!("Control socket: {}", path.display());
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::warn!("Control socket accept error: {}", err);
+                    continue;
+                }
+            };
+            let mut line = String::new();
+            if BufReader::new(&stream).read_line(&mut line).is_err() {
+                continue;
+            }
+            let reply = match Command::parse(&line) {
+                Some(command) => {
+                    log::info!("Control command: {:?}", command);
+                    handler(command).unwrap_or_else(|err| format!("error: {err}"))
+                }
+                None => format!("error: unknown command: {}", line.trim()),
+            };
+            let _ = writeln!(stream, "{reply}");
+        }
+    });
+    Ok(())
+}
+
+/// Send a command to the running server and return its reply
+pub fn send(path: &Path, command: Command) -> anyhow::Result<String> {
+    let mut stream = UnixStream::connect(path).context(format!(
+        "Failed to connect control socket: {}",
+        path.display()
+    ))?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    writeln!(stream, "{}", command.as_str())?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    let reply = reply.trim().to_owned();
+    if let Some(err) = reply.strip_prefix("error: ") {
+        anyhow::bail!("{err}");
+    }
+    Ok(reply)
+}
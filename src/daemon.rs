@@ -1,29 +1,89 @@
+use anyhow::Context;
 use daemonize::Daemonize;
+use nix::{
+    errno::Errno,
+    fcntl::{flock, FlockArg},
+};
 use std::{
-    fs::{File, Permissions},
-    os::unix::prelude::PermissionsExt,
+    fs::{File, OpenOptions, Permissions},
+    os::unix::prelude::{AsRawFd, OpenOptionsExt, PermissionsExt},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use std::{
-    io::{self, BufRead},
-    path::Path,
+    io::{self, BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
 };
 
-const PID_PATH: &str = "/var/run/thunder.pid";
 const DEFAULT_STDOUT_PATH: &str = "/var/run/thunder.out";
 const DEFAULT_STDERR_PATH: &str = "/var/run/thunder.err";
 const DEFAULT_WORK_DIR: &str = "/";
+const LOCK_PATH: &str = "/var/run/thunder.lock";
+/// Suffix of the file next to the pid file holding the time the server was last seen
+/// running, in seconds since the epoch
+const LAST_SEEN_SUFFIX: &str = ".last-seen";
+/// Suffix of the file next to the pid file holding the address the server listens on
+const LISTEN_SUFFIX: &str = ".listen";
+/// Exit code of `status` within the grace window after the server stopped
+pub(super) const STATUS_RESTARTING: u8 = 6;
+/// Exit code of `status` when the server is not running
+pub(super) const STATUS_DOWN: u8 = 7;
+/// Time to wait for another lifecycle command to finish
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Lock serializing the lifecycle commands, released on drop
+pub(super) struct Lock(File);
+
+/// Take the lock of install, uninstall, start and stop, waiting up to [`LOCK_TIMEOUT`]
+pub(super) fn lock() -> anyhow::Result<Lock> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(LOCK_PATH)
+        .context(format!("Failed to open lock file: {LOCK_PATH}"))?;
+    let started = Instant::now();
+    loop {
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => return Ok(Lock(file)),
+            Err(Errno::EWOULDBLOCK) if started.elapsed() < LOCK_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(100))
+            }
+            Err(Errno::EWOULDBLOCK) => anyhow::bail!(
+                "Another install, uninstall, start or stop is in progress, gave up after {}s",
+                LOCK_TIMEOUT.as_secs()
+            ),
+            Err(err) => return Err(err).context(format!("Failed to lock {LOCK_PATH}")),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // The daemon shares the lock with the `start` process it forked from
+        let _ = flock(self.0.as_raw_fd(), FlockArg::Unlock);
+    }
+}
 
 /// Check if the user is root
-pub fn check_root() {
+/// File next to the pid file, so that instances with their own pid file keep apart
+fn beside_pid(pid_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = pid_path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+pub fn check_root() -> anyhow::Result<()> {
     if !nix::unistd::Uid::effective().is_root() {
-        println!("You must run this executable with root permissions");
-        std::process::exit(-1)
+        return Err(anyhow::Error::new(crate::error::Failure::Permission)
+            .context("You must run this executable with root permissions"));
     }
+    Ok(())
 }
 
 /// Get the pid of the daemon
-pub(crate) fn get_pid() -> Option<String> {
-    if let Ok(data) = std::fs::read(PID_PATH) {
+pub(crate) fn get_pid(pid_path: &Path) -> Option<String> {
+    if let Ok(data) = std::fs::read(pid_path) {
         let binding = String::from_utf8(data).expect("pid file is not utf8");
         return Some(binding.trim().to_string());
     }
@@ -31,15 +91,15 @@ pub(crate) fn get_pid() -> Option<String> {
 }
 
 /// Start the daemon
-pub(super) fn start() -> anyhow::Result<()> {
-    if let Some(pid) = get_pid() {
-        println!("Thunder is already running with pid: {}", pid);
-        return Ok(());
+pub(super) fn start(pid_path: &Path) -> anyhow::Result<()> {
+    if let Some(pid) = get_pid(pid_path) {
+        return Err(anyhow::Error::new(crate::error::Failure::AlreadyRunning)
+            .context(format!("Thunder is already running with pid: {}", pid)));
     }
 
-    check_root();
+    check_root()?;
 
-    let pid_file = File::create(PID_PATH)?;
+    let pid_file = File::create(pid_path)?;
     pid_file.set_permissions(Permissions::from_mode(0o755))?;
 
     let stdout = File::create(DEFAULT_STDOUT_PATH)?;
@@ -49,7 +109,7 @@ pub(super) fn start() -> anyhow::Result<()> {
     stdout.set_permissions(Permissions::from_mode(0o755))?;
 
     let mut daemonize = Daemonize::new()
-        .pid_file(PID_PATH) // Every method except `new` and `start`
+        .pid_file(pid_path) // Every method except `new` and `start`
         .chown_pid_file(true) // is optional, see `Daemonize` documentation
         .working_directory(DEFAULT_WORK_DIR) // for default behaviour.
         .umask(0o777) // Set umask, `0o027` by default.
@@ -72,14 +132,34 @@ pub(super) fn start() -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Stop the daemon
-pub(super) fn stop() -> anyhow::Result<()> {
+/// Stop the daemon, or a foreground run, through the control socket or by signal
+pub(super) fn stop(pid_path: &Path, control_path: Option<&Path>) -> anyhow::Result<()> {
     use nix::sys::signal;
     use nix::unistd::Pid;
 
-    check_root();
+    check_root()?;
 
-    if let Some(pid) = get_pid() {
+    if let Some(control_path) = control_path.filter(|p| p.exists()) {
+        match crate::control::send(control_path, crate::control::Command::Shutdown) {
+            Ok(_) => {
+                // Wait for the server to exit
+                if let Some(pid) = get_pid(pid_path).and_then(|pid| pid.parse::<i32>().ok()) {
+                    for _ in 0..360 {
+                        if signal::kill(Pid::from_raw(pid), None).is_err() {
+                            break;
+                        }
+                        std::thread::sleep(std::time::Duration::from_secs(1))
+                    }
+                }
+                let _ = std::fs::remove_file(pid_path);
+                mark_last_seen(pid_path);
+                return Ok(());
+            }
+            Err(err) => log::debug!("Control socket shutdown failed: {}", err),
+        }
+    }
+
+    if let Some(pid) = get_pid(pid_path) {
         let pid = pid.parse::<i32>()?;
         for _ in 0..360 {
             if signal::kill(Pid::from_raw(pid), signal::SIGINT).is_err() {
@@ -87,21 +167,129 @@ pub(super) fn stop() -> anyhow::Result<()> {
             }
             std::thread::sleep(std::time::Duration::from_secs(1))
         }
-        let _ = std::fs::remove_file(PID_PATH);
+        let _ = std::fs::remove_file(pid_path);
+        mark_last_seen(pid_path);
     }
 
     Ok(())
 }
 
-/// Show the status of the daemon
-pub(super) fn status() -> anyhow::Result<()> {
-    match get_pid() {
-        Some(pid) => println!("Thunder is running with pid: {}", pid),
-        None => println!("Thunder is not running"),
+/// Register the foreground process in the pid file
+pub(super) fn register(pid_path: &Path) -> anyhow::Result<()> {
+    use nix::sys::signal;
+    use nix::unistd::Pid;
+
+    if let Some(pid) = get_pid(pid_path) {
+        // Ignore a stale pid file left by a process that is gone
+        if let Ok(pid) = pid.parse::<i32>() {
+            if signal::kill(Pid::from_raw(pid), None).is_ok() {
+                return Err(anyhow::Error::new(crate::error::Failure::AlreadyRunning)
+                    .context(format!("Thunder is already running with pid: {}", pid)));
+            }
+        }
     }
+    std::fs::write(pid_path, std::process::id().to_string())?;
     Ok(())
 }
 
+/// Remove the pid file written by `register`
+pub(super) fn unregister(pid_path: &Path) {
+    let _ = std::fs::remove_file(pid_path);
+    mark_last_seen(pid_path);
+}
+
+/// Record that the server was running until now
+pub(super) fn mark_last_seen(pid_path: &Path) {
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        let _ = std::fs::write(
+            beside_pid(pid_path, LAST_SEEN_SUFFIX),
+            now.as_secs().to_string(),
+        );
+    }
+    let _ = std::fs::remove_file(beside_pid(pid_path, LISTEN_SUFFIX));
+}
+
+/// Record the bound address, the port chosen by the OS when binding port 0
+pub(super) fn mark_listening(pid_path: &Path, addr: std::net::SocketAddr) {
+    let path = beside_pid(pid_path, LISTEN_SUFFIX);
+    if let Err(err) = std::fs::write(&path, addr.to_string()) {
+        log::warn!("Failed to write {}: {err}", path.display());
+    }
+}
+
+/// Show the status of the daemon, returns the exit code: 0 when running,
+/// [`STATUS_RESTARTING`] when it stopped less than `grace` ago, [`STATUS_DOWN`] otherwise
+pub(super) fn status(pid_path: &Path, grace: Duration) -> anyhow::Result<u8> {
+    use nix::sys::signal;
+    use nix::unistd::Pid;
+
+    if let Some(pid) = get_pid(pid_path) {
+        let alive = pid
+            .parse::<i32>()
+            .is_ok_and(|pid| signal::kill(Pid::from_raw(pid), None).is_ok());
+        if alive {
+            println!("Thunder is running with pid: {}", pid);
+            if let Ok(addr) = std::fs::read_to_string(beside_pid(pid_path, LISTEN_SUFFIX)) {
+                println!("Listening on: {}", addr.trim());
+            }
+            return Ok(0);
+        }
+    }
+
+    let stopped_for = std::fs::read_to_string(beside_pid(pid_path, LAST_SEEN_SUFFIX))
+        .ok()
+        .and_then(|secs| secs.trim().parse::<u64>().ok())
+        .and_then(|secs| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()?
+                .checked_sub(Duration::from_secs(secs))
+        });
+    match stopped_for {
+        Some(stopped_for) if stopped_for < grace => {
+            println!(
+                "Thunder is restarting, stopped {}s ago",
+                stopped_for.as_secs()
+            );
+            Ok(STATUS_RESTARTING)
+        }
+        _ => {
+            println!("Thunder is not running");
+            Ok(STATUS_DOWN)
+        }
+    }
+}
+
+/// Print the status every `interval` until interrupted, redrawn in place on a terminal
+/// and as successive snapshots separated by a blank line otherwise
+pub(super) fn watch(
+    pid_path: &Path,
+    grace: Duration,
+    interval: Duration,
+    download_path: Option<&Path>,
+) -> anyhow::Result<()> {
+    let tty = io::stdout().is_terminal();
+    loop {
+        if tty {
+            // Home the cursor and clear the screen
+            print!("\x1b[H\x1b[2J");
+        }
+        println!("{}", crate::util::format_utc(SystemTime::now()));
+        status(pid_path, grace)?;
+        if let Some(path) = download_path {
+            match crate::util::free_space(path) {
+                Ok(free) => println!("Download directory: {} ({free} bytes free)", path.display()),
+                Err(err) => println!("Download directory: {err}"),
+            }
+        }
+        if !tty {
+            println!();
+        }
+        io::stdout().flush()?;
+        std::thread::sleep(interval);
+    }
+}
+
 /// Show the log of the daemon
 pub(super) fn log() -> anyhow::Result<()> {
     fn read_and_print_file(file_path: &Path, placeholder: &str) -> anyhow::Result<()> {
@@ -118,6 +306,8 @@ pub(super) fn log() -> anyhow::Result<()> {
         let file = File::open(file_path)?;
         let reader = io::BufReader::new(file);
         let mut start = true;
+        // Older logs may contain colors
+        let color = crate::util::use_color(io::stdout().is_terminal());
 
         for line in reader.lines() {
             if let Ok(content) = line {
@@ -125,7 +315,10 @@ pub(super) fn log() -> anyhow::Result<()> {
                     start = false;
                     println!("{placeholder}");
                 }
-                println!("{}", content);
+                match color {
+                    true => println!("{}", content),
+                    false => println!("{}", crate::util::strip_ansi(&content)),
+                }
             } else if let Err(err) = line {
                 eprintln!("Error reading line: {}", err);
             }
@@ -0,0 +1,56 @@
+use std::fmt;
+
+/// Failures with a documented process exit code, any other error exits with 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// 2: missing or invalid configuration, also used by clap for bad arguments
+    Config,
+    /// 3: not running as root or permission denied
+    Permission,
+    /// 4: thunder is already running
+    AlreadyRunning,
+    /// 5: the download directory bind mount failed
+    Mount,
+}
+
+impl Failure {
+    pub fn code(&self) -> u8 {
+        match self {
+            Failure::Config => 2,
+            Failure::Permission => 3,
+            Failure::AlreadyRunning => 4,
+            Failure::Mount => 5,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Failure::Config => "configuration error",
+            Failure::Permission => "permission denied",
+            Failure::AlreadyRunning => "already running",
+            Failure::Mount => "mount error",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Exit code of an error
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    if let Some(failure) = err.downcast_ref::<Failure>() {
+        return failure.code();
+    }
+    let permission_denied = err.chain().any(|e| {
+        e.downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+            || e.downcast_ref::<nix::errno::Errno>()
+                .is_some_and(|e| matches!(e, nix::errno::Errno::EPERM | nix::errno::Errno::EACCES))
+    });
+    match permission_denied {
+        true => Failure::Permission.code(),
+        false => 1,
+    }
+}
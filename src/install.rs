@@ -1,6 +1,9 @@
 use std::ops::Not;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use rand::Rng;
@@ -11,6 +14,18 @@ use crate::util;
 use crate::InstallConfig;
 use crate::Running;
 
+/// Get the installed thunder version, or `pretend` when given
+pub(crate) fn installed_version(pretend: Option<&str>) -> Option<String> {
+    if let Some(version) = pretend {
+        return Some(version.to_owned());
+    }
+
+    std::fs::read_to_string(constant::SYNOPKG_VERSION)
+        .ok()
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+}
+
 /// Install xunlei
 pub struct XunleiInstall(pub InstallConfig);
 
@@ -18,157 +33,261 @@ impl Running for XunleiInstall {
     fn run(self) -> anyhow::Result<()> {
         // If the package is already installed, skip the installation
         if Path::new(constant::SYNOPKG_VAR).exists() {
-            println!("Thunder already installed");
+            match installed_version(self.0.pretend_version()) {
+                Some(version) => println!("Thunder {version} already installed"),
+                None => println!("Thunder already installed"),
+            }
             return Ok(());
         }
 
         println!("Installing in progress");
 
-        // config path
-        if self.0.config_path.is_dir().not() {
-            std::fs::create_dir_all(&self.0.config_path)?;
-            util::recursive_chown(&self.0.config_path, self.0.uid, self.0.gid);
-        } else if self.0.config_path.is_file() {
-            anyhow::bail!(
-                "Config path: {} must be a directory",
-                self.0.config_path.display()
-            )
+        let total = Duration::from_secs(self.0.install_timeout);
+        let per_step = Duration::from_secs(self.0.step_timeout);
+        let asset = Arc::new(Asset::new(
+            self.0.package.clone(),
+            self.0.quiet,
+            self.0.download_proxy.clone(),
+            Duration::from_secs(self.0.read_timeout),
+        )?);
+        let step = Arc::new(Step::new());
+        if total.is_zero() && per_step.is_zero() {
+            return install(self.0, &asset, &step);
         }
 
-        // real store download path
-        if self.0.download_path.is_dir().not() {
-            util::create_dir_all(&self.0.download_path, 0o755)?;
-            util::recursive_chown(&self.0.download_path, self.0.uid, self.0.gid);
-        } else if self.0.download_path.is_file() {
-            anyhow::bail!(
-                "Download path: {} must be a directory",
-                self.0.download_path.display()
-            )
+        // Run the install in a thread so a hung step can be abandoned
+        let base_existed = Path::new(constant::SYNOPKG_PKGBASE).exists();
+        let (tx, rx) = mpsc::channel();
+        {
+            let (config, asset, step) = (self.0.clone(), asset.clone(), step.clone());
+            std::thread::Builder::new()
+                .name("install".to_owned())
+                .spawn(move || {
+                    let _ = tx.send(install(config, &asset, &step));
+                })?;
+        }
+        let started = Instant::now();
+        loop {
+            match rx.recv_timeout(Duration::from_millis(200)) {
+                Ok(result) => return result,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    anyhow::bail!("Install step {} panicked", step.name())
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+            }
+            let (name, since) = step.current();
+            let err = if !total.is_zero() && started.elapsed() >= total {
+                anyhow::anyhow!(
+                    "Install timed out after {}s during step {name}",
+                    total.as_secs()
+                )
+            } else if !per_step.is_zero() && since.elapsed() >= per_step {
+                anyhow::anyhow!(
+                    "Install step {name} timed out after {}s",
+                    per_step.as_secs()
+                )
+            } else {
+                continue;
+            };
+            step.cancel();
+            println!("Cleaning up the partial installation");
+            asset.clean();
+            if !base_existed {
+                let _ = std::fs::remove_dir_all(constant::SYNOPKG_PKGBASE);
+            }
+            remove_if_symlink(Path::new(constant::SYNO_INFO_PATH))?;
+            remove_if_symlink(Path::new(constant::SYNO_AUTHENTICATE_PATH))?;
+            self.0.remove_file()?;
+            return Err(err);
         }
+    }
+}
 
-        // mount bind downloads directory
-        if self.0.mount_bind_download_path.is_dir().not() {
-            util::create_dir_all(&self.0.mount_bind_download_path, 0o755)?;
-            util::recursive_chown(&&self.0.mount_bind_download_path, self.0.uid, self.0.gid);
-        } else if self.0.mount_bind_download_path.is_file() {
-            anyhow::bail!(
-                "Mount bind download path: {} must be a directory",
-                self.0.mount_bind_download_path.display()
-            )
+/// Name and start time of the running install step
+struct Step {
+    current: Mutex<(&'static str, Instant)>,
+    cancelled: AtomicBool,
+}
+
+impl Step {
+    fn new() -> Self {
+        Self {
+            current: Mutex::new(("prepare", Instant::now())),
+            cancelled: AtomicBool::new(false),
         }
+    }
 
-        println!("Config directory: {}", self.0.config_path.display());
-        println!("Download directory: {}", self.0.download_path.display());
-
-        //  /var/packages/pan-xunlei-com
-        let base_dir = Path::new(constant::SYNOPKG_PKGBASE);
-        // /var/packages/pan-xunlei-com/target
-        let target_dir = PathBuf::from(constant::SYNOPKG_PKGDEST);
-        // /var/packages/pan-xunlei-com/target/host
-        let host_dir = PathBuf::from(constant::SYNOPKG_HOST);
-
-        // uid and gid
-        let uid = self.0.uid;
-        let gid = self.0.gid;
-
-        util::create_dir_all(&target_dir, 0o755)?;
-
-        // download xunlei binary
-        let xunlei = Asset::new(self.0.package)?;
-        xunlei.init()?;
-        for file in xunlei.iter()? {
-            let filename = file.as_str();
-            let target_filepath = target_dir.join(filename);
-            let data = xunlei.get(filename).context("Read data failure")?;
-            util::write_file(&target_filepath, data, 0o755)?;
-            println!("Install to: {}", target_filepath.display());
-            util::chown(&target_filepath, uid, gid).context(format!(
-                "Failed to set permission: {}, UID:{uid}, UID:{gid}",
-                base_dir.display(),
-            ))?;
+    /// Start the next step, failing once the install was abandoned
+    fn enter(&self, name: &'static str) -> anyhow::Result<()> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            anyhow::bail!("Install cancelled");
         }
+        *self.current.lock().unwrap() = (name, Instant::now());
+        Ok(())
+    }
 
-        // path: /var/packages/pan-xunlei-com/target/host/etc/synoinfo.conf
-        let synoinfo_path = PathBuf::from(format!(
-            "{}{}",
-            host_dir.display(),
-            constant::SYNO_INFO_PATH
-        ));
-        util::create_dir_all(
-            synoinfo_path.parent().context(format!(
-                "the path: {} parent not exists",
-                synoinfo_path.display()
-            ))?,
-            0o755,
-        )?;
-
-        // Generate a random synology id
-        let mut byte_arr = vec![0u8; 32];
-        rand::thread_rng().fill(&mut byte_arr[..]);
-        let hex_string = byte_arr
-            .iter()
-            .map(|u| format!("{:02x}", *u as u32))
-            .collect::<String>()
-            .chars()
-            .take(7)
-            .collect::<String>();
-        util::write_file(
-            &synoinfo_path,
-            std::borrow::Cow::Borrowed(
-                format!("unique=\"synology_{}_720+\"", hex_string).as_bytes(),
-            ),
-            0o644,
-        )?;
-
-        // path: /var/packages/pan-xunlei-com/target/host/usr/syno/synoman/webman/modules/authenticate.cgi
-        let syno_authenticate_path = PathBuf::from(format!(
-            "{}{}",
-            host_dir.display(),
-            constant::SYNO_AUTHENTICATE_PATH
-        ));
-        util::create_dir_all(
-            syno_authenticate_path.parent().context(format!(
-                "directory path: {} not exists",
-                syno_authenticate_path.display()
-            ))?,
-            0o755,
-        )?;
-        util::write_file(
-            &syno_authenticate_path,
-            std::borrow::Cow::Borrowed(String::from("#!/usr/bin/env sh\necho OK").as_bytes()),
-            0o755,
-        )?;
-
-        // path: /etc/synoinfo.conf
-        let target_synoinfo_path = Path::new(constant::SYNO_INFO_PATH);
-        nix::unistd::symlinkat(&synoinfo_path, None, target_synoinfo_path).context(format!(
-            "falied symlink {} to {}",
-            synoinfo_path.display(),
-            target_synoinfo_path.display()
+    fn current(&self) -> (&'static str, Instant) {
+        *self.current.lock().unwrap()
+    }
+
+    fn name(&self) -> &'static str {
+        self.current().0
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst)
+    }
+}
+
+fn install(config: InstallConfig, xunlei: &Asset, step: &Step) -> anyhow::Result<()> {
+    step.enter("prepare")?;
+    // config path
+    if config.config_path.is_dir().not() {
+        std::fs::create_dir_all(&config.config_path)?;
+        util::recursive_chown(&config.config_path, config.uid, config.gid);
+    } else if config.config_path.is_file() {
+        anyhow::bail!(
+            "Config path: {} must be a directory",
+            config.config_path.display()
+        )
+    }
+
+    // real store download path
+    if config.download_path.is_dir().not() {
+        util::create_dir_all(&config.download_path, 0o755)?;
+        util::recursive_chown(&config.download_path, config.uid, config.gid);
+    } else if config.download_path.is_file() {
+        anyhow::bail!(
+            "Download path: {} must be a directory",
+            config.download_path.display()
+        )
+    }
+
+    // mount bind downloads directory
+    if config.mount_bind_download_path.is_dir().not() {
+        util::create_dir_all(&config.mount_bind_download_path, 0o755)?;
+        util::recursive_chown(&config.mount_bind_download_path, config.uid, config.gid);
+    } else if config.mount_bind_download_path.is_file() {
+        anyhow::bail!(
+            "Mount bind download path: {} must be a directory",
+            config.mount_bind_download_path.display()
+        )
+    }
+
+    println!("Config directory: {}", config.config_path.display());
+    println!("Download directory: {}", config.download_path.display());
+
+    //  /var/packages/pan-xunlei-com
+    let base_dir = Path::new(constant::SYNOPKG_PKGBASE);
+    // /var/packages/pan-xunlei-com/target
+    let target_dir = PathBuf::from(constant::SYNOPKG_PKGDEST);
+    // /var/packages/pan-xunlei-com/target/host
+    let host_dir = PathBuf::from(constant::SYNOPKG_HOST);
+
+    // uid and gid
+    let uid = config.uid;
+    let gid = config.gid;
+
+    util::create_dir_all(&target_dir, 0o755)?;
+
+    // download xunlei binary
+    step.enter("download")?;
+    let package = xunlei.fetch()?;
+    step.enter("extract")?;
+    xunlei.extract(&package)?;
+    step.enter("install files")?;
+    for file in xunlei.iter()? {
+        let filename = file.as_str();
+        let target_filepath = target_dir.join(filename);
+        let data = xunlei.get(filename).context("Read data failure")?;
+        util::write_file(&target_filepath, data, 0o755)?;
+        println!("Install to: {}", target_filepath.display());
+        util::chown(&target_filepath, uid, gid).context(format!(
+            "Failed to set permission: {}, UID:{uid}, UID:{gid}",
+            base_dir.display(),
         ))?;
+    }
+
+    step.enter("host setup")?;
+    // path: /var/packages/pan-xunlei-com/target/host/etc/synoinfo.conf
+    let synoinfo_path = PathBuf::from(format!(
+        "{}{}",
+        host_dir.display(),
+        constant::SYNO_INFO_PATH
+    ));
+    util::create_dir_all(
+        synoinfo_path.parent().context(format!(
+            "the path: {} parent not exists",
+            synoinfo_path.display()
+        ))?,
+        0o755,
+    )?;
+
+    // Generate a random synology id
+    let mut byte_arr = [0u8; 32];
+    rand::thread_rng().fill(&mut byte_arr[..]);
+    let hex_string = byte_arr
+        .iter()
+        .map(|u| format!("{:02x}", *u as u32))
+        .collect::<String>()
+        .chars()
+        .take(7)
+        .collect::<String>();
+    util::write_file(
+        &synoinfo_path,
+        std::borrow::Cow::Borrowed(format!("unique=\"synology_{}_720+\"", hex_string).as_bytes()),
+        0o644,
+    )?;
 
-        // path: /usr/syno/synoman/webman/modules/authenticate.cgi
-        let target_syno_authenticate_path = Path::new(constant::SYNO_AUTHENTICATE_PATH);
-        let patent_ = target_syno_authenticate_path.parent().context(format!(
+    // path: /var/packages/pan-xunlei-com/target/host/usr/syno/synoman/webman/modules/authenticate.cgi
+    let syno_authenticate_path = PathBuf::from(format!(
+        "{}{}",
+        host_dir.display(),
+        constant::SYNO_AUTHENTICATE_PATH
+    ));
+    util::create_dir_all(
+        syno_authenticate_path.parent().context(format!(
             "directory path: {} not exists",
+            syno_authenticate_path.display()
+        ))?,
+        0o755,
+    )?;
+    util::write_file(
+        &syno_authenticate_path,
+        std::borrow::Cow::Borrowed(String::from("#!/usr/bin/env sh\necho OK").as_bytes()),
+        0o755,
+    )?;
+
+    // path: /etc/synoinfo.conf
+    let target_synoinfo_path = Path::new(constant::SYNO_INFO_PATH);
+    nix::unistd::symlinkat(&synoinfo_path, None, target_synoinfo_path).context(format!(
+        "falied symlink {} to {}",
+        synoinfo_path.display(),
+        target_synoinfo_path.display()
+    ))?;
+
+    // path: /usr/syno/synoman/webman/modules/authenticate.cgi
+    let target_syno_authenticate_path = Path::new(constant::SYNO_AUTHENTICATE_PATH);
+    let patent_ = target_syno_authenticate_path.parent().context(format!(
+        "directory path: {} not exists",
+        target_syno_authenticate_path.display()
+    ))?;
+    util::create_dir_all(patent_, 0o755)?;
+    nix::unistd::symlinkat(&syno_authenticate_path, None, target_syno_authenticate_path).context(
+        format!(
+            "falied symlink {} to {}",
+            syno_authenticate_path.display(),
             target_syno_authenticate_path.display()
-        ))?;
-        util::create_dir_all(patent_, 0o755)?;
-        nix::unistd::symlinkat(&syno_authenticate_path, None, target_syno_authenticate_path)
-            .context(format!(
-                "falied symlink {} to {}",
-                syno_authenticate_path.display(),
-                target_syno_authenticate_path.display()
-            ))?;
+        ),
+    )?;
 
-        // recursive base dir chown
-        util::recursive_chown(&base_dir, uid, gid);
+    // recursive base dir chown
+    util::recursive_chown(base_dir, uid, gid);
 
-        println!("Install to: {}, UID:{uid}, GID:{gid}", target_dir.display(),);
-        println!("Installation completed");
+    println!("Install to: {}, UID:{uid}, GID:{gid}", target_dir.display(),);
+    println!("Installation completed");
 
-        Ok(())
-    }
+    Ok(())
 }
 
 /// Uninstall xunlei
@@ -183,16 +302,6 @@ impl Running for XunleiUninstall {
             println!("Uninstall thunder package");
         }
 
-        fn remove_if_symlink(path: &Path) -> Result<(), std::io::Error> {
-            if let Ok(metadata) = std::fs::symlink_metadata(path) {
-                if metadata.file_type().is_symlink() {
-                    std::fs::remove_file(path)?;
-                    println!("Uninstall thunder {}", path.display());
-                }
-            }
-            Ok(())
-        }
-
         // Remove symlink
         remove_if_symlink(Path::new(constant::SYNO_INFO_PATH))?;
         remove_if_symlink(Path::new(constant::SYNO_AUTHENTICATE_PATH))?;
@@ -206,6 +315,21 @@ impl Running for XunleiUninstall {
             install_config.remove_file()?;
         }
 
+        let serve_path = crate::ServeConfig::path();
+        if serve_path.exists() {
+            std::fs::remove_file(serve_path)?;
+        }
+
         Ok(())
     }
 }
+
+fn remove_if_symlink(path: &Path) -> Result<(), std::io::Error> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_symlink() {
+            std::fs::remove_file(path)?;
+            println!("Uninstall thunder {}", path.display());
+        }
+    }
+    Ok(())
+}
@@ -1,17 +1,29 @@
 #[cfg(feature = "mimalloc")]
 #[global_allocator]
-static ALLOC: mimalloc::MiMalloc = mimalloc::MiMalloc;
+static ALLOC: alloc::Allocator = alloc::Allocator::new();
 
+#[cfg(feature = "mimalloc")]
+mod alloc;
 pub mod asset;
+mod config;
 pub mod constant;
+mod control;
 mod daemon;
+mod error;
 mod install;
+mod probe;
 mod serve;
+mod update;
 pub mod util;
 
-use clap::{Args, Parser, Subcommand};
+use clap::{
+    builder::BoolishValueParser, ArgAction, Args, CommandFactory, FromArgMatches, Parser,
+    Subcommand, ValueEnum,
+};
 use std::io::{BufRead, Write};
 use std::net::SocketAddr;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 
 pub trait Running {
@@ -20,8 +32,27 @@ pub trait Running {
 
 #[derive(Parser)]
 #[clap(author, version, about, arg_required_else_help = true)]
-#[command(args_conflicts_with_subcommands = true)]
 struct Opt {
+    /// Disable colored output, also set by NO_COLOR
+    #[clap(long, global = true)]
+    no_color: bool,
+    /// Directory of `*.conf` files merged over the install config, in lexical order
+    #[clap(
+        long,
+        global = true,
+        env = "THUNDER_CONFIG_DIR",
+        default_value = constant::DEFAULT_CONFIG_DIR
+    )]
+    config_dir: PathBuf,
+    /// Install config file, for systems where /etc is read-only; the serve options are
+    /// stored next to it with a `-serve` suffix
+    #[clap(
+        long,
+        global = true,
+        env = "THUNDER_CONFIG_FILE",
+        default_value = constant::DEFAULT_INSTALL_CONFIG
+    )]
+    config_file: PathBuf,
     #[clap(subcommand)]
     commands: Commands,
 }
@@ -37,11 +68,162 @@ pub enum Commands {
     /// Start thunder daemon
     Start(ServeConfig),
     /// Stop thunder daemon
-    Stop,
+    Stop(DaemonConfig),
     /// Show the Http server daemon process
-    Status,
+    Status(StatusConfig),
     /// Show the Http server daemon log
     Log,
+    /// Show the audit log of mutating requests
+    Audit(AuditConfig),
+    /// Pause all downloads of the running thunder, its UI hangs until resumed
+    Pause,
+    /// Resume all downloads of the running thunder
+    Resume,
+    /// Turn the maintenance page of the running thunder on or off
+    Maintenance {
+        #[clap(value_enum)]
+        state: Toggle,
+    },
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    /// Print every setting with its default value as a commented config file
+    DumpDefaultConfig,
+    /// Update thunder to the latest release
+    SelfUpdate(SelfUpdateConfig),
+    /// Check whether newer releases are available, exits with 8 when there is one
+    CheckUpdate(CheckUpdateConfig),
+    /// Check that the servers of thunder are reachable from this network
+    Probe(ProbeConfig),
+    /// Set a new authentication password and session key, logging out every session
+    RotateAuth(RotateAuthConfig),
+    /// Load and verify the TLS certificates and keys without starting the server
+    CheckTls(CheckTlsConfig),
+}
+
+#[derive(Args, Clone)]
+pub struct CheckTlsConfig {
+    /// Mark certificates expiring within this many days
+    #[clap(long, default_value = "30")]
+    warn_days: i64,
+    #[clap(flatten)]
+    serve: ServeConfig,
+}
+
+#[derive(Args, Clone)]
+pub struct RotateAuthConfig {
+    /// New authentication password, generated and printed once when omitted
+    #[clap(long)]
+    password: Option<String>,
+}
+
+#[derive(Args, Clone)]
+pub struct AuditConfig {
+    /// Number of last entries to show
+    #[clap(short = 'n', long, default_value = "20")]
+    lines: usize,
+    /// Keep printing new entries
+    #[clap(short, long)]
+    follow: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct SelfUpdateConfig {
+    /// Release channel to follow
+    #[clap(long, value_enum, default_value = "stable")]
+    channel: Channel,
+    /// Allow updating across major versions
+    #[clap(long)]
+    force: bool,
+    /// Only check whether an update is available
+    #[clap(long)]
+    check: bool,
+    /// Proxy of the release downloads, overrides HTTPS_PROXY and NO_PROXY
+    #[clap(long, env = "THUNDER_DOWNLOAD_PROXY", value_parser = util::parse_proxy)]
+    download_proxy: Option<String>,
+    /// Releases API endpoint
+    #[cfg(feature = "test-utils")]
+    #[clap(long, hide = true)]
+    releases_url: Option<String>,
+}
+
+#[derive(Args, Clone)]
+pub struct CheckUpdateConfig {
+    /// Release channel to follow
+    #[clap(long, value_enum, default_value = "stable")]
+    channel: Channel,
+    /// Proxy of the release queries, overrides HTTPS_PROXY and NO_PROXY
+    #[clap(long, env = "THUNDER_DOWNLOAD_PROXY", value_parser = util::parse_proxy)]
+    download_proxy: Option<String>,
+    /// Releases API endpoint
+    #[cfg(feature = "test-utils")]
+    #[clap(long, hide = true)]
+    releases_url: Option<String>,
+    /// Pretend this thunder version is installed
+    #[cfg(feature = "test-utils")]
+    #[clap(long, hide = true)]
+    pretend_version: Option<String>,
+}
+
+impl CheckUpdateConfig {
+    /// Version passed with `--pretend-version`
+    #[cfg(feature = "test-utils")]
+    fn pretend_version(&self) -> Option<&str> {
+        self.pretend_version.as_deref()
+    }
+
+    #[cfg(not(feature = "test-utils"))]
+    fn pretend_version(&self) -> Option<&str> {
+        None
+    }
+}
+
+#[derive(Args, Clone)]
+pub struct ProbeConfig {
+    /// Server to check as host:port, repeatable, defaults to the servers of thunder
+    #[clap(long)]
+    target: Vec<String>,
+    /// Connect timeout in seconds of each server
+    #[clap(long, default_value = "5")]
+    timeout: u64,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Toggle {
+    On,
+    Off,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Channel {
+    /// Stable releases only
+    Stable,
+    /// Stable and prerelease releases
+    Prerelease,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Show the resolved configuration and the source of each value
+    Show(Box<ConfigShowArgs>),
+    /// Print the location of the install config file
+    Path(ConfigPathArgs),
+}
+
+#[derive(Args, Clone)]
+pub struct ConfigPathArgs {
+    /// Print the stored serve options file instead
+    #[clap(long)]
+    serve: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct ConfigShowArgs {
+    /// Print as JSON
+    #[clap(long)]
+    json: bool,
+    #[clap(flatten)]
+    serve: ServeConfig,
 }
 
 #[derive(Args, Clone)]
@@ -63,25 +245,222 @@ pub struct InstallConfig {
     /// Thunder mount bind download directory
     #[clap(short, long, default_value = constant::DEFAULT_BIND_DOWNLOAD_PATH)]
     mount_bind_download_path: PathBuf,
+    /// Hide download and extraction progress
+    #[clap(short, long)]
+    quiet: bool,
+    /// Mode of the install config file, in octal
+    #[clap(long, default_value = "600", value_parser = parse_mode)]
+    config_permissions: u32,
+    /// Allow the download directory and its mount bind directory to contain each other
+    #[clap(long)]
+    allow_nested_paths: bool,
+    /// Proxy of the package download, overrides HTTP_PROXY and NO_PROXY
+    #[clap(long, env = "THUNDER_DOWNLOAD_PROXY", value_parser = util::parse_proxy)]
+    download_proxy: Option<String>,
+    /// Abort the install after this many seconds, 0 disables it
+    #[clap(long, env = "THUNDER_INSTALL_TIMEOUT", default_value = "0")]
+    install_timeout: u64,
+    /// Abort the install when a single step takes this many seconds, 0 disables it
+    #[clap(long, env = "THUNDER_STEP_TIMEOUT", default_value = "0")]
+    step_timeout: u64,
+    /// Fail the install when a single read of the package takes this many seconds, 0 waits
+    /// forever
+    #[clap(long, env = "THUNDER_READ_TIMEOUT", default_value = "60")]
+    read_timeout: u64,
+    /// Pretend this thunder version is installed
+    #[cfg(feature = "test-utils")]
+    #[clap(long, hide = true)]
+    pretend_version: Option<String>,
+    /// Paths that went through a symlink, with their real paths
+    #[clap(skip)]
+    resolved_symlinks: Vec<(PathBuf, PathBuf)>,
+    /// Where the effective paths come from, set by `override_paths`
+    #[clap(skip)]
+    path_sources: Vec<PathSource>,
+}
+
+/// Effective install path and where it comes from
+#[derive(Clone)]
+struct PathSource {
+    name: &'static str,
+    source: &'static str,
+    /// Path of the install config when the effective one differs from it
+    install_time: Option<PathBuf>,
 }
 
+/// Drop-in directory of the install config, set once from `--config-dir`
+static CONFIG_DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+/// Install config file, set once from `--config-file`
+static CONFIG_FILE: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
 impl InstallConfig {
-    const PATH: &'static str = "/etc/.thunder";
+    /// Version passed with `--pretend-version`
+    #[cfg(feature = "test-utils")]
+    fn pretend_version(&self) -> Option<&str> {
+        self.pretend_version.as_deref()
+    }
+
+    #[cfg(not(feature = "test-utils"))]
+    fn pretend_version(&self) -> Option<&str> {
+        None
+    }
+
+    /// Install config file
+    fn path() -> &'static Path {
+        CONFIG_FILE
+            .get()
+            .map(PathBuf::as_path)
+            .unwrap_or(Path::new(constant::DEFAULT_INSTALL_CONFIG))
+    }
+
+    /// Replace the config, download and mount bind directories with their real paths,
+    /// so the mounts never depend on which side of a symlink they are applied to
+    fn resolve_symlinks(&mut self) -> anyhow::Result<()> {
+        for path in [
+            &mut self.config_path,
+            &mut self.download_path,
+            &mut self.mount_bind_download_path,
+        ] {
+            let resolved = util::resolve_symlinks(path)?;
+            if resolved != util::absolute(path)? {
+                self.resolved_symlinks
+                    .push((path.clone(), resolved.clone()));
+            }
+            *path = resolved;
+        }
+        Ok(())
+    }
+
+    /// Apply the path options of the serve command over the install-time paths, a flag
+    /// wins over the environment, which wins over the stored serve config. A path that
+    /// differs from the install-time one is reported, the directories were prepared and
+    /// owned for the latter.
+    fn override_paths(
+        &mut self,
+        serve_config: &ServeConfig,
+        matches: &clap::ArgMatches,
+    ) -> anyhow::Result<()> {
+        let mut sources = Vec::new();
+        for (id, name, path, value) in [
+            (
+                "config_path",
+                "config directory",
+                &mut self.config_path,
+                &serve_config.config_path,
+            ),
+            (
+                "download_path",
+                "download directory",
+                &mut self.download_path,
+                &serve_config.download_path,
+            ),
+            (
+                "mount_bind_download_path",
+                "mount bind directory",
+                &mut self.mount_bind_download_path,
+                &serve_config.mount_bind_download_path,
+            ),
+        ] {
+            let Some(value) = value else {
+                sources.push(PathSource {
+                    name,
+                    source: "install config",
+                    install_time: None,
+                });
+                continue;
+            };
+            let source = match matches.value_source(id) {
+                Some(clap::parser::ValueSource::CommandLine) => "flag",
+                Some(clap::parser::ValueSource::EnvVariable) => "env",
+                _ => "stored serve config",
+            };
+            let value = util::resolve_symlinks(value)?;
+            let install_time = (value != *path).then(|| std::mem::replace(path, value));
+            if let Some(ref install_time) = install_time {
+                eprintln!(
+                    "Warning: the {name} {} from the {source} is not the install-time {}",
+                    path.display(),
+                    install_time.display()
+                );
+            }
+            sources.push(PathSource {
+                name,
+                source,
+                install_time,
+            });
+        }
+        self.path_sources = sources;
+        Ok(())
+    }
+
+    /// Refuse a download directory and mount bind directory inside one another, the bind
+    /// mount would then show the downloads within themselves
+    fn check_paths(&self) -> anyhow::Result<()> {
+        if self.allow_nested_paths {
+            return Ok(());
+        }
+        let download = util::absolute(&self.download_path)?;
+        let mount = util::absolute(&self.mount_bind_download_path)?;
+        let (inner, outer) = match (download.starts_with(&mount), mount.starts_with(&download)) {
+            (true, true) => {
+                return Err(anyhow::Error::new(error::Failure::Config).context(format!(
+                    "The download directory and the mount bind directory are both {}",
+                    download.display()
+                )))
+            }
+            (true, false) => (("download", &download), ("mount bind", &mount)),
+            (false, true) => (("mount bind", &mount), ("download", &download)),
+            (false, false) => return Ok(()),
+        };
+        Err(anyhow::Error::new(error::Failure::Config).context(format!(
+            "The {} directory {} is inside the {} directory {}, downloads would appear within themselves through the bind mount; install with --allow-nested-paths to allow it",
+            inner.0,
+            inner.1.display(),
+            outer.0,
+            outer.1.display()
+        )))
+    }
+
+    /// Drop-in files merged over the install config, in lexical order
+    fn drop_ins() -> anyhow::Result<Vec<PathBuf>> {
+        let dir = CONFIG_DIR
+            .get()
+            .map(PathBuf::as_path)
+            .unwrap_or(Path::new(constant::DEFAULT_CONFIG_DIR));
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut files = std::fs::read_dir(dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf") && path.is_file())
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        Ok(files)
+    }
 
     /// Remove config file
     pub fn remove_file(self) -> anyhow::Result<()> {
-        let path = Path::new(Self::PATH);
+        let path = Self::path();
         if path.exists() {
-            std::fs::remove_file(&Self::PATH)?;
+            std::fs::remove_file(path)?;
         }
         Ok(())
     }
 
     /// Write to file
     fn write_to_file(&self) -> anyhow::Result<()> {
-        let path = Path::new(Self::PATH);
+        let path = Self::path();
         if !path.exists() {
-            let mut file = std::fs::File::create(path)?;
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(self.config_permissions)
+                .open(path)
+                .map_err(|err| util::write_error(path, err))?;
+            // Not affected by the umask
+            file.set_permissions(std::fs::Permissions::from_mode(self.config_permissions))?;
+            nix::unistd::fchown(file.as_raw_fd(), Some(0.into()), Some(0.into()))?;
             writeln!(file, "uid={}", self.uid)?;
             writeln!(file, "gid={}", self.gid)?;
             writeln!(file, "config_path={}", self.config_path.display())?;
@@ -91,17 +470,48 @@ impl InstallConfig {
                 "mount_bind_download_path={}",
                 self.mount_bind_download_path.display()
             )?;
+            if self.allow_nested_paths {
+                writeln!(file, "allow_nested_paths=true")?;
+            }
             file.flush()?;
             drop(file)
         }
         Ok(())
     }
 
+    /// Install config with the path options of the serve config applied, the paths where
+    /// a server started without flags keeps its control socket and audit log
+    fn read_runtime() -> anyhow::Result<Self> {
+        let mut install_config = Self::read_from_file()?;
+        let matches = ServeConfig::augment_args(clap::Command::new("serve"))
+            .try_get_matches_from(["serve"])?;
+        let serve_config = config::resolve_serve("serve", &matches)?;
+        for (path, value) in [
+            (&mut install_config.config_path, serve_config.config_path),
+            (
+                &mut install_config.download_path,
+                serve_config.download_path,
+            ),
+            (
+                &mut install_config.mount_bind_download_path,
+                serve_config.mount_bind_download_path,
+            ),
+        ] {
+            if let Some(value) = value {
+                *path = util::resolve_symlinks(&value)?;
+            }
+        }
+        Ok(install_config)
+    }
+
     /// Read from file
     fn read_from_file() -> anyhow::Result<Self> {
-        let path = Path::new(Self::PATH);
+        let path = Self::path();
         if !path.exists() {
-            anyhow::bail!("`{}` not found", path.display());
+            return Err(anyhow::Error::new(error::Failure::Config).context(format!(
+                "`{}` not found, is thunder installed?",
+                path.display()
+            )));
         }
 
         let mut uid = 0;
@@ -109,13 +519,27 @@ impl InstallConfig {
         let mut config_path = PathBuf::new();
         let mut download_path = PathBuf::new();
         let mut mount_bind_download_path = PathBuf::new();
+        let mut allow_nested_paths = false;
 
-        let file = std::fs::File::open(&Self::PATH)?;
+        let file = std::fs::File::open(path)?;
+        if file.metadata()?.permissions().mode() & 0o004 != 0 {
+            eprintln!(
+                "Warning: {} is readable by any user, consider `chmod 600 {}`",
+                path.display(),
+                path.display()
+            );
+        }
         let reader = std::io::BufReader::new(file);
-        for line in reader.lines() {
-            let line = line?;
+        let mut lines = reader.lines().collect::<Result<Vec<String>, _>>()?;
+        // Later files win
+        for drop_in in Self::drop_ins()? {
+            let content = std::fs::read_to_string(&drop_in)
+                .map_err(|err| anyhow::anyhow!("Failed to read {}: {err}", drop_in.display()))?;
+            lines.extend(content.lines().map(str::to_owned));
+        }
+        for line in lines {
             let line = line.trim();
-            if line.is_empty() {
+            if line.is_empty() || line.starts_with('#') {
                 continue;
             }
             let mut split = line.split('=');
@@ -137,28 +561,108 @@ impl InstallConfig {
                 "mount_bind_download_path" => {
                     mount_bind_download_path = value.parse()?;
                 }
+                "allow_nested_paths" => {
+                    allow_nested_paths = value.parse()?;
+                }
                 _ => {}
             }
         }
 
-        Ok(Self {
+        let mut config = Self {
             uid,
             gid,
             config_path,
             download_path,
             mount_bind_download_path,
             package: None,
-        })
+            quiet: false,
+            download_proxy: None,
+            config_permissions: 0o600,
+            allow_nested_paths,
+            install_timeout: 0,
+            step_timeout: 0,
+            read_timeout: 0,
+            #[cfg(feature = "test-utils")]
+            pretend_version: None,
+            resolved_symlinks: Vec::new(),
+            path_sources: Vec::new(),
+        };
+        config.resolve_symlinks()?;
+        Ok(config)
     }
 }
 #[derive(Args, Clone)]
+pub struct DaemonConfig {
+    /// Daemon PID file
+    #[clap(long, env = "THUNDER_PIDFILE", default_value = constant::DEFAULT_PID_PATH)]
+    pidfile: PathBuf,
+}
+
+#[derive(Args, Clone)]
+pub struct StatusConfig {
+    #[clap(flatten)]
+    daemon: DaemonConfig,
+    /// Report a server stopped less than this many seconds ago as restarting
+    #[clap(long, env = "THUNDER_STATUS_GRACE", default_value = "0")]
+    status_grace: u64,
+    /// Keep printing the status until interrupted
+    #[clap(long)]
+    watch: bool,
+    /// Seconds between two status updates of --watch
+    #[clap(long, default_value = "2", requires = "watch", value_parser = clap::value_parser!(u64).range(1..))]
+    interval: u64,
+}
+
+/// Options of the server. Switches take an optional value, so that one stored by an
+/// earlier run can be turned off with `--switch=false`.
+#[derive(Args, Clone)]
 pub struct ServeConfig {
     /// enable debug
     #[clap(long, env = "THUNDER_DEBUG")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
     debug: bool,
+    /// Truncate log lines longer than this, e.g. 16K, 0 disables it
+    #[clap(long, env = "THUNDER_MAX_LOG_LINE", default_value = "16K", value_parser = util::parse_size)]
+    max_log_line: u64,
+    /// Also write the log to the stderr of the starting process, e.g. for journald
+    #[clap(long, env = "THUNDER_LOG_ALSO_STDERR")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    log_also_stderr: bool,
     /// Authentication password
     #[arg(short = 'w', long, env = "THUNDER_AUTH_PASS")]
     auth_password: Option<String>,
+    /// Serve CPU flamegraphs at /debug/pprof/profile to admins
+    #[cfg(feature = "pprof")]
+    #[clap(long, env = "THUNDER_ENABLE_PPROF")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    enable_pprof: bool,
+    /// Serve the TLS parameters of the current connection at /debug/tls
+    #[clap(long, env = "THUNDER_ENABLE_DEBUG_ENDPOINTS")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    enable_debug_endpoints: bool,
+    /// Read only authentication password
+    #[arg(long, env = "THUNDER_VIEWER_PASS")]
+    viewer_password: Option<String>,
+    /// Block every request that would modify tasks
+    #[clap(long, env = "THUNDER_READ_ONLY_UI")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    read_only_ui: bool,
+    /// Start with the UI answering 503 and the maintenance page
+    #[clap(long, env = "THUNDER_MAINTENANCE")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    maintenance: bool,
+    /// HTML file served in maintenance mode
+    #[clap(long, env = "THUNDER_MAINTENANCE_PAGE")]
+    maintenance_page: Option<PathBuf>,
+    /// Authentication mode
+    #[clap(long, env = "THUNDER_AUTH_MODE", value_enum, default_value = "form")]
+    auth_mode: AuthMode,
+    /// Session signing key, defaults to the authentication password
+    #[clap(long, env = "THUNDER_SESSION_KEY")]
+    session_key: Option<String>,
+    /// Session cookie lifetime in seconds
+    #[clap(long, env = "THUNDER_SESSION_TTL", default_value = "86400")]
+    session_ttl: u64,
     /// Thunder server bind address
     #[clap(
         short = 'B',
@@ -167,42 +671,528 @@ pub struct ServeConfig {
         default_value = "0.0.0.0:5055"
     )]
     bind: SocketAddr,
-    /// TLS certificate file
-    #[clap(short = 'C', long, env = "THUNDER_TLS_CERT")]
-    tls_cert: Option<PathBuf>,
-    /// TLS private key file
-    #[clap(short = 'K', long, env = "THUNDER_TLS_KEY")]
-    tls_key: Option<PathBuf>,
+    /// Only accept connections on this network interface (SO_BINDTODEVICE)
+    #[clap(long, env = "THUNDER_BIND_INTERFACE")]
+    bind_interface: Option<String>,
+    /// Expect a PROXY protocol v1/v2 header on every connection and use its client address
+    #[clap(long, env = "THUNDER_PROXY_PROTOCOL")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    proxy_protocol: bool,
+    /// Reverse proxies, as IP addresses or networks, whose client address header is honored
+    #[clap(
+        long,
+        env = "THUNDER_TRUSTED_PROXY",
+        value_delimiter = ',',
+        value_parser = serve::real_ip::parse_cidr
+    )]
+    trusted_proxy: Vec<serve::real_ip::Cidr>,
+    /// Header carrying the client address set by a trusted proxy
+    #[clap(
+        long,
+        env = "THUNDER_REAL_IP_HEADER",
+        value_enum,
+        default_value = "x-forwarded-for"
+    )]
+    real_ip_header: RealIpHeader,
+    /// TLS certificate file, repeat with --tls-key to select certificates by SNI
+    #[clap(short = 'C', long, env = "THUNDER_TLS_CERT", value_delimiter = ',')]
+    tls_cert: Vec<PathBuf>,
+    /// TLS private key file, one for each --tls-cert in the same order
+    #[clap(short = 'K', long, env = "THUNDER_TLS_KEY", value_delimiter = ',')]
+    tls_key: Vec<PathBuf>,
+    /// TLS certificate chain and private key in a PKCS#12 (.p12/.pfx) bundle
+    #[clap(long, env = "THUNDER_TLS_PKCS12", conflicts_with_all = ["tls_cert", "tls_key"])]
+    tls_pkcs12: Option<PathBuf>,
+    /// Password of the PKCS#12 bundle
+    #[clap(long, env = "THUNDER_TLS_PKCS12_PASSWORD", requires = "tls_pkcs12")]
+    tls_pkcs12_password: Option<String>,
+    /// Require client certificates issued by this CA, they authenticate as admin
+    #[clap(long, env = "THUNDER_TLS_CLIENT_CA")]
+    tls_client_ca: Option<PathBuf>,
+    /// Client certificate common names that only get read only access
+    #[clap(
+        long,
+        env = "THUNDER_TLS_CLIENT_VIEWER",
+        value_delimiter = ',',
+        requires = "tls_client_ca"
+    )]
+    tls_client_viewer: Vec<String>,
+    /// Minimum TLS protocol version
+    #[clap(
+        long,
+        env = "THUNDER_TLS_MIN_VERSION",
+        value_enum,
+        default_value = "1.2"
+    )]
+    tls_min_version: TlsVersion,
+    /// Allowed TLS cipher suites, comma separated, e.g. TLS13_AES_256_GCM_SHA384
+    #[clap(long, env = "THUNDER_TLS_CIPHERS", value_delimiter = ',')]
+    tls_ciphers: Vec<String>,
+    /// Staple the OCSP response of the TLS certificate, refreshed in the background
+    #[clap(long, env = "THUNDER_OCSP_STAPLE")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    ocsp_staple: bool,
+    /// Add Strict-Transport-Security to responses when TLS is enabled
+    #[clap(long, env = "THUNDER_HSTS")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    hsts: bool,
+    /// Let search engines index the UI, by default robots.txt and X-Robots-Tag forbid it
+    #[clap(long, env = "THUNDER_ALLOW_INDEXING")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    allow_indexing: bool,
+    /// Extra response header "Name: Value", can be repeated
+    #[clap(long, value_parser = parse_header)]
+    response_header: Vec<(axum::http::HeaderName, axum::http::HeaderValue)>,
+    /// Limit thunder CPU usage in cores, e.g. 1.5 (cgroup v2)
+    #[clap(long, env = "THUNDER_CPU_LIMIT", value_parser = parse_cpu_limit)]
+    cpu_limit: Option<f64>,
+    /// Limit thunder memory usage, e.g. 512M (cgroup v2)
+    #[clap(long, env = "THUNDER_MEMORY_LIMIT", value_parser = util::parse_size)]
+    memory_limit: Option<u64>,
+    /// Raise the open files limit (RLIMIT_NOFILE) of thunder
+    #[clap(long, env = "THUNDER_MAX_OPEN_FILES")]
+    max_open_files: Option<u64>,
+    /// CPU niceness of thunder, from -20 (highest) to 19 (lowest)
+    #[clap(
+        long,
+        env = "THUNDER_NICE",
+        allow_hyphen_values = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19)
+    )]
+    nice: Option<i32>,
+    /// IO priority of thunder: idle, best-effort[:0-7] or realtime[:0-7]
+    #[clap(long, env = "THUNDER_IONICE", value_parser = serve::priority::parse_ionice)]
+    ionice: Option<serve::priority::IoNice>,
+    /// Daemon PID file
+    #[clap(long, env = "THUNDER_PIDFILE", default_value = constant::DEFAULT_PID_PATH)]
+    pidfile: PathBuf,
+    /// Report files under the download directory not owned by the configured uid/gid
+    #[clap(long, env = "THUNDER_CHECK_OWNERSHIP")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    check_ownership: bool,
+    /// Chown files under the download directory not owned by the configured uid/gid
+    #[clap(long, env = "THUNDER_FIX_OWNERSHIP")]
+    #[clap(action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true", default_value = "false", value_parser = BoolishValueParser::new())]
+    fix_ownership: bool,
+    /// Interval in seconds between ownership checks
+    #[clap(
+        long,
+        env = "THUNDER_OWNERSHIP_INTERVAL",
+        default_value = "3600",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    ownership_interval: u64,
+    /// SELinux context of the download directory and its files, such as
+    /// system_u:object_r:public_content_t:s0
+    #[clap(long, env = "THUNDER_SELINUX_CONTEXT", value_parser = serve::selinux::parse_context)]
+    selinux_context: Option<String>,
+    /// Thunder config directory instead of the install-time one
+    #[clap(long, env = "THUNDER_CONFIG_PATH")]
+    config_path: Option<PathBuf>,
+    /// Thunder download directory instead of the install-time one
+    #[clap(long, env = "THUNDER_DOWNLOAD_PATH")]
+    download_path: Option<PathBuf>,
+    /// Thunder mount bind download directory instead of the install-time one
+    #[clap(long, env = "THUNDER_MOUNT_BIND_DOWNLOAD_PATH")]
+    mount_bind_download_path: Option<PathBuf>,
+    /// Read-only directory shown under the download directory with an overlay mount,
+    /// new downloads are written to the download directory
+    #[clap(long, env = "THUNDER_OVERLAY")]
+    overlay: Option<PathBuf>,
+    /// Work directory of the overlay mount, on the filesystem of the download directory,
+    /// defaults to a hidden sibling of the download directory
+    #[clap(long, env = "THUNDER_OVERLAY_WORKDIR", requires = "overlay")]
+    overlay_workdir: Option<PathBuf>,
+    /// Retries of the download directory bind mount
+    #[clap(long, env = "THUNDER_MOUNT_RETRIES", default_value = "3")]
+    mount_retries: u32,
+    /// Initial delay in seconds between bind mount and unmount retries, doubled on each retry
+    #[clap(long, env = "THUNDER_MOUNT_RETRY_DELAY", default_value = "1")]
+    mount_retry_delay: u64,
+    /// Retries of the unmount of the download directory on stop while it is busy, it is
+    /// then detached lazily
+    #[clap(long, env = "THUNDER_UNMOUNT_RETRIES", default_value = "3")]
+    unmount_retries: u32,
+    /// Seconds to wait before mounting the download directory and starting thunder
+    #[clap(long, env = "THUNDER_STARTUP_DELAY", default_value = "0")]
+    startup_delay: u64,
+    /// Wait before starting until this path is a mountpoint
+    #[clap(long, env = "THUNDER_WAIT_FOR_PATH")]
+    wait_for_path: Option<PathBuf>,
+    /// Seconds to wait for --wait-for-path, 0 waits forever
+    #[clap(long, env = "THUNDER_WAIT_FOR_PATH_TIMEOUT", default_value = "300")]
+    wait_for_path_timeout: u64,
+    /// Thunder launcher executable, defaults to the installed one
+    #[clap(long, env = "THUNDER_BIN")]
+    thunder_bin: Option<PathBuf>,
+    /// Thunder web CGI executable
+    #[cfg(feature = "test-utils")]
+    #[clap(long, hide = true)]
+    thunder_web_bin: Option<PathBuf>,
+    /// Extra environment variable of thunder as KEY=VALUE, repeatable
+    #[clap(long, value_parser = serve::child_env::parse)]
+    thunder_env: Vec<(String, String)>,
+    /// File of extra KEY=VALUE environment variables of thunder, one per line,
+    /// overridden by --thunder-env
+    #[clap(long, env = "THUNDER_ENV_FILE")]
+    thunder_env_file: Option<PathBuf>,
+    /// Health check mode of /healthz
+    #[clap(
+        long,
+        env = "THUNDER_HEALTH_MODE",
+        value_enum,
+        default_value = "shallow"
+    )]
+    health_mode: HealthMode,
+    /// Minimum free space of the download directory in deep health check
+    #[clap(long, env = "THUNDER_HEALTH_MIN_FREE", default_value = "1G", value_parser = util::parse_size)]
+    health_min_free: u64,
+    /// Pause thunder while the download directory has less free space, resume once freed
+    #[clap(long, env = "THUNDER_MIN_FREE_SPACE", value_parser = util::parse_size)]
+    min_free_space: Option<u64>,
+    /// Timeout in seconds of requests to thunder, a stuck request answers 504
+    #[clap(long, env = "THUNDER_UPSTREAM_TIMEOUT", default_value = "30")]
+    upstream_timeout: u64,
+    /// Timeout in seconds of a whole request, an expired request answers 408, 0 disables it
+    #[clap(long, env = "THUNDER_REQUEST_TIMEOUT", default_value = "60")]
+    request_timeout: u64,
+    /// Path prefixes without the request timeout, comma separated
+    #[clap(
+        long,
+        env = "THUNDER_REQUEST_TIMEOUT_EXEMPT",
+        value_delimiter = ',',
+        default_value = "/debug/pprof"
+    )]
+    request_timeout_exempt: Vec<String>,
+    /// Timeout in seconds of the TLS handshake of a new connection, 0 disables it
+    #[clap(long, env = "THUNDER_HANDSHAKE_TIMEOUT", default_value = "10")]
+    handshake_timeout: u64,
+    /// Language of the thunder web UI, overrides the browser Accept-Language
+    #[clap(long, env = "THUNDER_UI_LANG", value_enum)]
+    ui_lang: Option<UiLang>,
+}
+
+impl ServeConfig {
+    /// Options stored by the last run or start, next to the install config
+    fn path() -> PathBuf {
+        let mut path = InstallConfig::path().as_os_str().to_owned();
+        path.push("-serve");
+        PathBuf::from(path)
+    }
+
+    /// Thunder launcher executable
+    fn launcher_exe(&self) -> PathBuf {
+        self.thunder_bin
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(constant::LAUNCHER_EXE))
+    }
+
+    /// Work directory of the overlay mount over `download_path`
+    fn overlay_workdir(&self, download_path: &Path) -> PathBuf {
+        self.overlay_workdir.clone().unwrap_or_else(|| {
+            let name = download_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            download_path.with_file_name(format!(".{name}.overlay-work"))
+        })
+    }
+
+    /// Whether the server listens with TLS
+    fn tls_enabled(&self) -> bool {
+        self.tls_pkcs12.is_some() || !self.tls_cert.is_empty()
+    }
+
+    /// Thunder web CGI executable
+    fn web_exe(&self) -> PathBuf {
+        #[cfg(feature = "test-utils")]
+        if let Some(ref path) = self.thunder_web_bin {
+            return path.clone();
+        }
+        PathBuf::from(constant::SYNOPKG_CLI_WEB)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum AuthMode {
+    /// HTML login page with a session cookie
+    Form,
+    /// HTTP basic authentication
+    Basic,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum RealIpHeader {
+    /// X-Forwarded-For, the last address not added by a trusted proxy
+    XForwardedFor,
+    /// X-Real-IP
+    XRealIp,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum HealthMode {
+    /// Thunder process is alive
+    Shallow,
+    /// Also check free space and that the launcher responds
+    Deep,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    /// TLS 1.2 and 1.3
+    #[value(name = "1.2")]
+    Tls12,
+    /// TLS 1.3 only
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum UiLang {
+    /// Simplified Chinese
+    #[value(name = "zh-CN")]
+    ZhCn,
+    /// Traditional Chinese
+    #[value(name = "zh-TW")]
+    ZhTw,
+    /// English
+    #[value(name = "en-US")]
+    EnUs,
+}
+
+impl UiLang {
+    /// Accept-Language value sent to the thunder web UI
+    fn accept_language(&self) -> &'static str {
+        match self {
+            UiLang::ZhCn => "zh-CN",
+            UiLang::ZhTw => "zh-TW",
+            UiLang::EnUs => "en-US",
+        }
+    }
+}
+
+fn parse_header(s: &str) -> anyhow::Result<(axum::http::HeaderName, axum::http::HeaderValue)> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Header must be in the form \"Name: Value\""))?;
+    Ok((name.trim().parse()?, value.trim().parse()?))
+}
+
+fn parse_mode(s: &str) -> anyhow::Result<u32> {
+    let mode = u32::from_str_radix(s.trim_start_matches("0o"), 8)?;
+    if mode > 0o777 {
+        anyhow::bail!("Mode must be between 0 and 777");
+    }
+    Ok(mode)
+}
+
+fn parse_cpu_limit(s: &str) -> anyhow::Result<f64> {
+    let cpu_limit = s.parse::<f64>()?;
+    if !cpu_limit.is_finite() || cpu_limit <= 0.0 {
+        anyhow::bail!("CPU limit must be a positive number of cores");
+    }
+    Ok(cpu_limit)
 }
 
-fn main() -> anyhow::Result<()> {
-    let opt = Opt::parse();
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            std::process::ExitCode::from(error::exit_code(&err))
+        }
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let matches = Opt::command().get_matches();
+    let opt = Opt::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    util::init_color(opt.no_color);
+    #[cfg(feature = "mimalloc")]
+    if ALLOC.fell_back() {
+        eprintln!("Warning: mimalloc failed to allocate, using the system allocator");
+    }
+    let _ = CONFIG_DIR.set(opt.config_dir);
+    let _ = CONFIG_FILE.set(opt.config_file);
 
     match opt.commands {
-        Commands::Install(config) => {
+        Commands::Install(mut config) => {
+            config.resolve_symlinks()?;
+            for (path, resolved) in &config.resolved_symlinks {
+                println!(
+                    "Resolved symlink {} to {}",
+                    path.display(),
+                    resolved.display()
+                );
+            }
+            config.check_paths()?;
+            let _lock = daemon::lock()?;
             config.write_to_file()?;
             install::XunleiInstall(config).run()?;
         }
         Commands::Uninstall => {
-            let install_config = InstallConfig::read_from_file().map_or(None, |v| Some(v));
+            let _lock = daemon::lock()?;
+            let install_config = InstallConfig::read_from_file().ok();
             install::XunleiUninstall(install_config).run()?;
         }
-        Commands::Run(config) => {
-            serve::Serve::new(config, InstallConfig::read_from_file()?).run()?;
+        Commands::Run(_) => {
+            let config = config::persist_serve("run", subcommand_matches(&matches, "run"))?;
+            let mut install_config = InstallConfig::read_from_file()?;
+            install_config.override_paths(&config, subcommand_matches(&matches, "run"))?;
+            install_config.check_paths()?;
+            let pid_path = config.pidfile.clone();
+            serve::preflight(&config)?;
+            daemon::register(&pid_path)?;
+            let result = serve::Serve::new(config, install_config).run();
+            daemon::unregister(&pid_path);
+            result?;
         }
-        Commands::Start(config) => {
-            daemon::start()?;
-            serve::Serve::new(config, InstallConfig::read_from_file()?).run()?;
+        Commands::Start(_) => {
+            let config = config::persist_serve("start", subcommand_matches(&matches, "start"))?;
+            let mut install_config = InstallConfig::read_from_file()?;
+            install_config.override_paths(&config, subcommand_matches(&matches, "start"))?;
+            install_config.check_paths()?;
+            serve::preflight(&config)?;
+            let lock = daemon::lock()?;
+            if config.log_also_stderr {
+                serve::keep_stderr()?;
+            }
+            daemon::start(&config.pidfile)?;
+            let pid_path = config.pidfile.clone();
+            let result = serve::Serve::new(config, install_config)
+                .holding(lock)
+                .run();
+            daemon::mark_last_seen(&pid_path);
+            result?;
+        }
+        Commands::Stop(config) => {
+            let _lock = daemon::lock()?;
+            let control_path = InstallConfig::read_runtime()
+                .ok()
+                .map(|v| control::sock_path(&v.config_path));
+            daemon::stop(&config.pidfile, control_path.as_deref())?;
         }
-        Commands::Stop => {
-            daemon::stop()?;
+        Commands::Status(config) if config.watch => {
+            let download_path = InstallConfig::read_runtime()
+                .ok()
+                .map(|install| install.download_path);
+            daemon::watch(
+                &config.daemon.pidfile,
+                std::time::Duration::from_secs(config.status_grace),
+                std::time::Duration::from_secs(config.interval),
+                download_path.as_deref(),
+            )?;
         }
-        Commands::Status => {
-            daemon::status()?;
+        Commands::Status(config) => {
+            let code = daemon::status(
+                &config.daemon.pidfile,
+                std::time::Duration::from_secs(config.status_grace),
+            )?;
+            if code != 0 {
+                std::process::exit(code.into());
+            }
         }
         Commands::Log => {
             daemon::log()?;
         }
+        Commands::Audit(config) => {
+            let install_config = InstallConfig::read_runtime()?;
+            serve::audit::tail(&install_config.config_path, config.lines, config.follow)?;
+        }
+        Commands::Pause => {
+            println!("{}", send_control(control::Command::Pause)?);
+        }
+        Commands::Resume => {
+            println!("{}", send_control(control::Command::Resume)?);
+        }
+        Commands::Maintenance { state } => {
+            let command = match state {
+                Toggle::On => control::Command::MaintenanceOn,
+                Toggle::Off => control::Command::MaintenanceOff,
+            };
+            println!("{}", send_control(command)?);
+        }
+        Commands::Config(ConfigCommands::Show(args)) => {
+            let matches = matches
+                .subcommand_matches("config")
+                .and_then(|m| m.subcommand_matches("show"))
+                .expect("config show matches");
+            config::show(matches, args.json)?;
+        }
+        Commands::Config(ConfigCommands::Path(args)) => match args.serve {
+            true => println!("{}", ServeConfig::path().display()),
+            false => println!("{}", InstallConfig::path().display()),
+        },
+        Commands::DumpDefaultConfig => {
+            config::dump_defaults();
+        }
+        Commands::SelfUpdate(config) => {
+            update::SelfUpdate(config).run()?;
+        }
+        Commands::CheckUpdate(config) => {
+            let thunder = install::installed_version(config.pretend_version());
+            if update::check_update(config, thunder)? {
+                std::process::exit(update::UPDATE_AVAILABLE);
+            }
+        }
+        Commands::Probe(config) => {
+            probe::Probe(config).run()?;
+        }
+        Commands::CheckTls(config) => {
+            let serve =
+                config::resolve_serve("check-tls", subcommand_matches(&matches, "check-tls"))?;
+            serve::check_tls(&serve, config.warn_days)?;
+        }
+        Commands::RotateAuth(config) => {
+            daemon::check_root()?;
+            let password = match config.password {
+                Some(password) => password,
+                None => {
+                    let password = util::random_string(24);
+                    println!("New password: {password}");
+                    password
+                }
+            };
+            config::store_auth(&password, &util::random_string(32))?;
+            for (id, name) in [
+                ("auth_password", "password"),
+                ("session_key", "session key"),
+            ] {
+                if let Some(env) =
+                    config::env_name(id).filter(|env| std::env::var_os(env).is_some())
+                {
+                    eprintln!("Warning: {env} overrides the stored {name} on start");
+                }
+            }
+            println!("{}", send_control(control::Command::ReloadAuth)?);
+        }
     }
     Ok(())
 }
+
+/// Send a command to the running server, a no-op when it is not running
+fn send_control(command: control::Command) -> anyhow::Result<String> {
+    let control_path = InstallConfig::read_runtime()
+        .ok()
+        .map(|v| control::sock_path(&v.config_path))
+        .filter(|p| p.exists());
+    match control_path {
+        Some(path) => match control::send(&path, command) {
+            Err(err) if is_not_running(&err) => Ok(String::from("Thunder is not running")),
+            result => result,
+        },
+        None => Ok(String::from("Thunder is not running")),
+    }
+}
+
+fn is_not_running(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<std::io::Error>())
+        .any(|e| e.kind() == std::io::ErrorKind::ConnectionRefused)
+}
+
+fn subcommand_matches<'a>(matches: &'a clap::ArgMatches, name: &str) -> &'a clap::ArgMatches {
+    matches
+        .subcommand_matches(name)
+        .expect("subcommand matches")
+}
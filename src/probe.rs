@@ -0,0 +1,64 @@
+use std::{
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use crate::ProbeConfig;
+
+/// Servers of thunder checked when no `--target` is given: the package mirror,
+/// the cloud API and the account service
+const TARGETS: [&str; 3] = [
+    "down.sandai.net:80",
+    "api-pan.xunlei.com:443",
+    "xluser-ssl.xunlei.com:443",
+];
+
+pub struct Probe(pub ProbeConfig);
+
+impl Probe {
+    /// Resolve and connect to every target, printing the latency of each step.
+    /// Fails when any target is unreachable.
+    pub fn run(self) -> anyhow::Result<()> {
+        let timeout = Duration::from_secs(self.0.timeout);
+        let targets = match self.0.target.is_empty() {
+            true => TARGETS.iter().map(|t| t.to_string()).collect(),
+            false => self.0.target,
+        };
+        let mut failed = 0;
+        for target in &targets {
+            match probe(target, timeout) {
+                Ok((addr, resolve, connect)) => println!(
+                    "{target} ({}): dns {}ms, connect {}ms",
+                    addr.ip(),
+                    resolve.as_millis(),
+                    connect.as_millis()
+                ),
+                Err(err) => {
+                    failed += 1;
+                    println!("{target}: {err}")
+                }
+            }
+        }
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} targets are unreachable", targets.len())
+        }
+        Ok(())
+    }
+}
+
+/// Resolve `target` and connect to its first address, returns the address and the
+/// time taken by each step
+fn probe(target: &str, timeout: Duration) -> anyhow::Result<(SocketAddr, Duration, Duration)> {
+    let start = Instant::now();
+    let addr = target
+        .to_socket_addrs()
+        .map_err(|err| anyhow::anyhow!("dns failed: {err}"))?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("dns failed: no address"))?;
+    let resolve = start.elapsed();
+
+    let start = Instant::now();
+    TcpStream::connect_timeout(&addr, timeout)
+        .map_err(|err| anyhow::anyhow!("connect to {} failed: {err}", addr.ip()))?;
+    Ok((addr, resolve, start.elapsed()))
+}
@@ -0,0 +1,79 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    net::SocketAddr,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Audit log file name in the config directory
+const FILE_NAME: &str = "audit.log";
+
+/// Location of the audit log
+pub(crate) fn path(config_path: &Path) -> PathBuf {
+    config_path.join(FILE_NAME)
+}
+
+/// Append a mutating request that passed authentication to the audit log
+pub(super) fn record(
+    config_path: &Path,
+    user: &str,
+    client: Option<SocketAddr>,
+    action: &str,
+    target: &str,
+    status: u16,
+) {
+    let client = client
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| String::from("-"));
+    let line = format!(
+        "{} user={user} client={client} action={action} target={target} status={status}\n",
+        crate::util::format_utc(SystemTime::now())
+    );
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path(config_path))
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(err) = result {
+        log::warn!("Failed to write the audit log: {err}");
+    }
+}
+
+/// Print the last `lines` of the audit log, then keep printing new entries when `follow`
+pub(crate) fn tail(config_path: &Path, lines: usize, follow: bool) -> anyhow::Result<()> {
+    let path = path(config_path);
+    let mut offset = 0;
+    if let Ok(content) = std::fs::read_to_string(&path) {
+        let all = content.lines().collect::<Vec<&str>>();
+        for line in &all[all.len().saturating_sub(lines)..] {
+            println!("{line}");
+        }
+        offset = content.len() as u64;
+    }
+
+    if !follow {
+        return Ok(());
+    }
+    loop {
+        std::thread::sleep(Duration::from_secs(1));
+        let Ok(mut file) = File::open(&path) else {
+            continue;
+        };
+        let len = file.metadata()?.len();
+        // Start over when the log was truncated or replaced
+        if len < offset {
+            offset = 0;
+        }
+        if len > offset {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut new = String::new();
+            file.read_to_string(&mut new)?;
+            print!("{new}");
+            std::io::stdout().flush()?;
+            offset += new.len() as u64;
+        }
+    }
+}
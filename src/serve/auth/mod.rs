@@ -1,13 +1,95 @@
-use std::sync::OnceLock;
-
-use tokio::sync::OnceCell;
+use std::{collections::HashMap, sync::Mutex, sync::OnceLock, sync::RwLock};
 
 pub mod murmur;
 pub mod token;
 
-/// Check auth
-pub(super) static CHECK_AUTH: OnceCell<Option<String>> = OnceCell::const_new();
-/// Token secret
-static TOKEN_SECRET: OnceLock<String> = OnceLock::new();
-/// Token expire
-pub(super) const EXP: u64 = 3600 * 24;
+/// Check auth, replaced by `rotate-auth`
+static CHECK_AUTH: RwLock<Option<String>> = RwLock::new(None);
+/// Check viewer auth
+static VIEWER_AUTH: RwLock<Option<String>> = RwLock::new(None);
+/// Token secret, replaced by `rotate-auth`
+static TOKEN_SECRET: RwLock<Option<String>> = RwLock::new(None);
+/// Logged out tokens and their expiry
+static REVOKED: OnceLock<Mutex<HashMap<String, u64>>> = OnceLock::new();
+
+/// Permission level of an authenticated request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    /// Full access
+    Admin,
+    /// Read only access
+    Viewer,
+}
+
+impl Role {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s {
+            "admin" => Some(Role::Admin),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+}
+
+/// Set the passwords and the session key, which defaults to the password
+pub(super) fn init(
+    auth_password: Option<String>,
+    viewer_password: Option<String>,
+    session_key: Option<String>,
+) -> anyhow::Result<()> {
+    if let Some(key) = session_key
+        .or_else(|| auth_password.clone())
+        .or_else(|| viewer_password.clone())
+    {
+        *TOKEN_SECRET.write().expect("token secret lock") = Some(secret(&key));
+    }
+    *CHECK_AUTH.write().expect("auth lock") = auth_password;
+    *VIEWER_AUTH.write().expect("auth lock") = viewer_password;
+    Ok(())
+}
+
+/// Replace the password and the session key, every issued session becomes invalid
+pub(super) fn rotate(auth_password: String, session_key: String) {
+    *TOKEN_SECRET.write().expect("token secret lock") = Some(secret(&session_key));
+    *CHECK_AUTH.write().expect("auth lock") = Some(auth_password);
+    if let Some(revoked) = REVOKED.get() {
+        revoked.lock().expect("revoked lock").clear();
+    }
+}
+
+/// Token signing secret derived from a session key
+fn secret(key: &str) -> String {
+    let (x, y) = murmur::murmurhash3_x64_128(key.as_bytes(), 31);
+    format!("{x}{y}")
+}
+
+/// Whether any password is configured
+pub(super) fn enabled() -> bool {
+    CHECK_AUTH.read().expect("auth lock").is_some()
+        || VIEWER_AUTH.read().expect("auth lock").is_some()
+}
+
+/// Authenticate a password, returning its role
+pub(super) fn authentication(password: &str) -> Option<Role> {
+    if !enabled() {
+        return Some(Role::Admin);
+    }
+    if let Some(ref p) = *CHECK_AUTH.read().expect("auth lock") {
+        if password.eq(p) {
+            return Some(Role::Admin);
+        }
+    }
+    if let Some(ref p) = *VIEWER_AUTH.read().expect("auth lock") {
+        if password.eq(p) {
+            return Some(Role::Viewer);
+        }
+    }
+    None
+}
@@ -7,13 +7,13 @@ fn fmix64(mut k: u64) -> u64 {
     k = k.wrapping_mul(0xc4ceb9fe1a85ec53u64);
     k ^= k >> 33;
 
-    return k;
+    k
 }
 
 fn get_128_block(bytes: &[u8], index: usize) -> (u64, u64) {
     let b64: &[u64] = unsafe { mem::transmute(bytes) };
 
-    return (b64[index], b64[index + 1]);
+    (b64[index], b64[index + 1])
 }
 
 pub fn murmurhash3_x64_128(bytes: &[u8], seed: u64) -> (u64, u64) {
@@ -99,7 +99,7 @@ pub fn murmurhash3_x64_128(bytes: &[u8], seed: u64) -> (u64, u64) {
         k1 ^= (bytes[(block_count * read_size) as usize + 1] as u64) << 8;
     }
     if len & 15 >= 1 {
-        k1 ^= bytes[(block_count * read_size) as usize + 0] as u64;
+        k1 ^= bytes[(block_count * read_size) as usize] as u64;
         k1 = k1.wrapping_mul(c1);
         k1 = k1.rotate_left(31);
         k1 = k1.wrapping_mul(c2);
@@ -118,5 +118,5 @@ pub fn murmurhash3_x64_128(bytes: &[u8], seed: u64) -> (u64, u64) {
     h1 = h1.wrapping_add(h2);
     h2 = h2.wrapping_add(h1);
 
-    return (h1, h2);
+    (h1, h2)
 }
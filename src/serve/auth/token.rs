@@ -1,37 +1,71 @@
 use jsonwebtokens::{encode, Algorithm, AlgorithmID, Verifier};
 use std::{collections::HashMap, time::Duration};
 
-use super::{CHECK_AUTH, EXP, TOKEN_SECRET};
-
-fn get_or_init_secret() -> &'static String {
-    TOKEN_SECRET.get_or_init(|| {
-        let secret = if let Some(Some(auth_password)) = CHECK_AUTH.get() {
-            auth_password.to_owned()
-        } else {
-            generate_random_string(31)
-        };
-        let (x, y) = super::murmur::murmurhash3_x64_128(secret.as_bytes(), 31);
-        format!("{x}{y}")
-    })
+use super::{Role, REVOKED, TOKEN_SECRET};
+
+fn get_or_init_secret() -> String {
+    let mut secret = TOKEN_SECRET.write().expect("token secret lock");
+    secret
+        .get_or_insert_with(|| super::secret(&crate::util::random_string(31)))
+        .clone()
 }
 
-pub fn generate_token() -> anyhow::Result<String> {
+pub fn generate_token(exp: u64, role: Role) -> anyhow::Result<String> {
     let s = get_or_init_secret();
-    let alg = Algorithm::new_hmac(AlgorithmID::HS256, s.to_owned())?;
+    let alg = Algorithm::new_hmac(AlgorithmID::HS256, s)?;
 
     let mut header = HashMap::new();
     let mut claims = HashMap::new();
     header.insert("alg".to_owned(), alg.name().to_owned());
-    claims.insert("exp".to_owned(), now_duration()?.as_secs() + EXP);
+    claims.insert(
+        "exp".to_owned(),
+        serde_json::Value::from(now_duration()?.as_secs() + exp),
+    );
+    claims.insert("role".to_owned(), serde_json::Value::from(role.as_str()));
 
     Ok(encode(&header, &claims, &alg)?)
 }
 
-pub fn verifier(token_str: &str) -> anyhow::Result<()> {
+pub fn verifier(token_str: &str) -> anyhow::Result<Role> {
+    let s = get_or_init_secret();
+    let alg = Algorithm::new_hmac(AlgorithmID::HS256, s)?;
+    let verifier = Verifier::create().build()?;
+    let claims = verifier.verify(token_str, &alg)?;
+    if let Some(revoked) = REVOKED.get() {
+        if revoked
+            .lock()
+            .expect("revoked lock")
+            .contains_key(token_str)
+        {
+            anyhow::bail!("token has been revoked");
+        }
+    }
+    // Tokens issued before roles existed carry full access
+    Ok(claims
+        .get("role")
+        .and_then(|role| role.as_str())
+        .and_then(Role::parse)
+        .unwrap_or(Role::Admin))
+}
+
+/// Revoke a token until it expires
+pub fn revoke(token_str: &str) -> anyhow::Result<()> {
     let s = get_or_init_secret();
-    let alg = Algorithm::new_hmac(AlgorithmID::HS256, s.to_owned())?;
+    let alg = Algorithm::new_hmac(AlgorithmID::HS256, s)?;
     let verifier = Verifier::create().build()?;
-    let _ = verifier.verify(&token_str, &alg)?;
+    let claims = verifier.verify(token_str, &alg)?;
+    let exp = claims
+        .get("exp")
+        .and_then(|exp| exp.as_u64())
+        .unwrap_or_default();
+
+    let now = now_duration()?.as_secs();
+    let mut revoked = REVOKED
+        .get_or_init(Default::default)
+        .lock()
+        .expect("revoked lock");
+    revoked.retain(|_, exp| *exp > now);
+    revoked.insert(token_str.to_owned(), exp);
     Ok(())
 }
 
@@ -40,14 +74,3 @@ fn now_duration() -> anyhow::Result<Duration> {
     let duration = now.duration_since(std::time::UNIX_EPOCH)?;
     Ok(duration)
 }
-
-fn generate_random_string(len: usize) -> String {
-    use rand::distributions::Alphanumeric;
-    use rand::{thread_rng, Rng};
-    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
-    let rng = thread_rng();
-    rng.sample_iter(&Alphanumeric)
-        .take(len)
-        .map(|x| CHARSET[x as usize % CHARSET.len()] as char)
-        .collect()
-}
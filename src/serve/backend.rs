@@ -1,29 +1,53 @@
-#[cfg(target_os = "linux")]
-use nix::mount::MsFlags;
+use anyhow::Context;
 use nix::sys::signal;
 use nix::unistd::Pid;
 use signal_hook::iterator::Signals;
 use std::os::unix::process::CommandExt;
 
+use crate::serve::cgroup::Cgroup;
 use crate::serve::ConfigExt;
 use crate::{constant, InstallConfig, Running};
 use crate::{util, ServeConfig};
-use std::{ops::Not, path::Path, process::Stdio};
+use std::{
+    ops::Not,
+    path::Path,
+    process::{Child, Stdio},
+    time::Duration,
+};
 
-pub(super) struct BackendServer(ServeConfig, InstallConfig, tokio::sync::mpsc::Sender<()>);
+/// Time given to thunder to exit when it is stopped or restarted
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(super) struct BackendServer(
+    ServeConfig,
+    InstallConfig,
+    tokio::sync::mpsc::Sender<()>,
+    std::sync::mpsc::Receiver<()>,
+    Option<crate::daemon::Lock>,
+);
 
 impl BackendServer {
+    /// `graceful_shutdown` asks the frontend to stop, which answers on `drained` once
+    /// its in-flight requests are done
     pub(super) fn new(
         serve_config: ServeConfig,
         install_config: InstallConfig,
         graceful_shutdown: tokio::sync::mpsc::Sender<()>,
+        drained: std::sync::mpsc::Receiver<()>,
+        lock: Option<crate::daemon::Lock>,
     ) -> Self {
-        Self(serve_config, install_config, graceful_shutdown)
+        Self(
+            serve_config,
+            install_config,
+            graceful_shutdown,
+            drained,
+            lock,
+        )
     }
 }
 
 impl Running for BackendServer {
-    fn run(self) -> anyhow::Result<()> {
+    fn run(mut self) -> anyhow::Result<()> {
         // If Synology NAS is not installed, the backend service will not be started
         let var_path = Path::new(constant::SYNOPKG_VAR);
         if var_path.exists().not() {
@@ -31,78 +55,95 @@ impl Running for BackendServer {
             util::chown(var_path, self.1.uid, self.1.gid)?;
         }
 
+        // Give slow storage time to appear
+        if self.0.startup_delay > 0 {
+            log::info!("Startup delay: {}s", self.0.startup_delay);
+            std::thread::sleep(Duration::from_secs(self.0.startup_delay));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(ref path) = self.0.wait_for_path {
+            super::mount::wait_for(path, Duration::from_secs(self.0.wait_for_path_timeout))
+                .context(crate::error::Failure::Mount)?;
+        }
+
         #[cfg(target_os = "linux")]
         let _ = nix::mount::umount(&self.1.mount_bind_download_path);
         #[cfg(target_os = "linux")]
-        if nix::mount::mount(
-            Some(&self.1.download_path),
-            &self.1.mount_bind_download_path,
-            <Option<&'static [u8]>>::None,
-            MsFlags::MS_BIND,
-            <Option<&'static [u8]>>::None,
-        )
-        .is_err()
-        {
-            anyhow::bail!(
-                "Mount {} to {} failed",
-                self.1.download_path.display(),
-                self.1.mount_bind_download_path.display()
-            );
+        match self.0.overlay {
+            Some(ref lower) => super::mount::overlay(
+                lower,
+                &self.1.download_path,
+                &self.0.overlay_workdir(&self.1.download_path),
+                &self.1.mount_bind_download_path,
+                self.0.mount_retries,
+                Duration::from_secs(self.0.mount_retry_delay),
+            ),
+            None => super::mount::bind(
+                &self.1.download_path,
+                &self.1.mount_bind_download_path,
+                self.0.mount_retries,
+                Duration::from_secs(self.0.mount_retry_delay),
+            ),
         }
+        .context(crate::error::Failure::Mount)?;
 
-        // environment variables
-        let envs = (&self.0, &self.1).envs()?;
-
-        log::info!("Start Thunder Backend Server");
-        let mut cmd = std::process::Command::new(constant::LAUNCHER_EXE);
-        cmd.args([
-            format!("-launcher_listen={}", constant::LAUNCHER_SOCK),
-            format!("-pid={}", constant::PID_FILE),
-            format!("-logfile={}", constant::LAUNCH_LOG_FILE),
-        ])
-        .current_dir(constant::SYNOPKG_PKGDEST)
-        .envs(envs)
-        .uid(self.1.uid)
-        .gid(self.1.gid);
-
-        // If debug is false, hide stderr, stdin, stdout
-        if !self.0.debug {
-            cmd.stderr(Stdio::null())
-                .stdin(Stdio::null())
-                .stdout(Stdio::null());
+        // Raise the open files limit inherited by thunder
+        if let Some(max_open_files) = self.0.max_open_files {
+            raise_nofile_limit(max_open_files)?;
         }
 
-        // Start the backend service
-        let backend_process = cmd.spawn()?;
+        // Limit thunder resources with cgroup v2
+        let cgroup = Cgroup::new(self.0.cpu_limit, self.0.memory_limit)?;
 
-        // Backend service PID
-        let backend_pid = backend_process.id() as i32;
-        log::info!("Thunder Backend Server PID: {backend_pid}");
+        // Start the backend service
+        let mut backend_process = self.spawn(cgroup.as_ref())?;
+        // Started, let the waiting lifecycle commands go
+        drop(self.4.take());
 
         let mut signals = Signals::new([
             signal_hook::consts::SIGINT,
             signal_hook::consts::SIGHUP,
             signal_hook::consts::SIGTERM,
+            signal_hook::consts::SIGUSR1,
+            signal_hook::consts::SIGUSR2,
         ])?;
 
         // Receive signal
+        let mut result = Ok(());
         for signal in signals.forever() {
             match signal {
+                signal_hook::consts::SIGUSR1 => super::state::dump(&self.0, &self.1),
+                // Restart thunder, the mount and the frontend stay up
+                signal_hook::consts::SIGUSR2 => {
+                    log::info!("Restarting the Thunder Backend Server");
+                    stop(&mut backend_process);
+                    match self.spawn(cgroup.as_ref()) {
+                        Ok(process) => backend_process = process,
+                        Err(err) => {
+                            self.2.blocking_send(())?;
+                            result =
+                                Err(err.context("Failed to restart the Thunder Backend Server"));
+                            break;
+                        }
+                    }
+                }
                 signal_hook::consts::SIGINT
                 | signal_hook::consts::SIGHUP
                 | signal_hook::consts::SIGTERM => {
-                    // Send a signal to the backend service to terminate
+                    // Stop accepting connections and let the in-flight requests,
+                    // which may still need thunder, finish first
                     self.2.blocking_send(())?;
-
-                    let kill_pid = Pid::from_raw(backend_pid);
-
-                    // Wait for the backend service to terminate
-                    let kill = signal::kill(kill_pid, signal::SIGINT);
-                    if let Some(err) = kill.err() {
-                        log::error!("The backend kill error: {}", err);
-                        signal::kill(kill_pid, signal::SIGTERM)?;
+                    if self
+                        .3
+                        .recv_timeout(super::frontend::DRAIN_TIMEOUT + Duration::from_secs(1))
+                        .is_err()
+                    {
+                        log::warn!("The frontend did not stop in time");
                     }
 
+                    // Then wait for thunder to exit before unmounting its downloads
+                    log::info!("Stopping the Thunder Backend Server");
+                    stop(&mut backend_process);
                     break;
                 }
                 _ => {
@@ -113,13 +154,121 @@ impl Running for BackendServer {
 
         // umount bind directory
         #[cfg(target_os = "linux")]
-        if nix::mount::umount(&self.1.mount_bind_download_path).is_err() {
-            log::error!(
-                "Unmount {} failed",
-                self.1.mount_bind_download_path.display()
-            )
+        if let Err(err) = super::mount::unmount(
+            &self.1.mount_bind_download_path,
+            self.0.unmount_retries,
+            Duration::from_secs(self.0.mount_retry_delay),
+        ) {
+            log::error!("{err}")
         }
 
-        Ok(())
+        if let Some(cgroup) = cgroup {
+            cgroup.remove();
+        }
+
+        result
     }
 }
+
+impl BackendServer {
+    /// Start the thunder launcher in its own process group
+    fn spawn(&self, cgroup: Option<&Cgroup>) -> anyhow::Result<Child> {
+        // environment variables
+        let envs = (&self.0, &self.1).envs()?;
+
+        log::info!("Start Thunder Backend Server");
+        let mut cmd = std::process::Command::new(self.0.launcher_exe());
+        cmd.args([
+            format!("-launcher_listen={}", constant::LAUNCHER_SOCK),
+            format!("-pid={}", constant::PID_FILE),
+            format!("-logfile={}", constant::LAUNCH_LOG_FILE),
+        ])
+        .current_dir(constant::SYNOPKG_PKGDEST)
+        .envs(envs)
+        .uid(self.1.uid)
+        .gid(self.1.gid)
+        .process_group(0);
+
+        // If debug is false, hide stderr, stdin, stdout
+        match self.0.debug {
+            true => cmd.stderr(Stdio::piped()).stdout(Stdio::piped()),
+            false => cmd
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .stdout(Stdio::null()),
+        };
+
+        if let Some(cgroup) = cgroup {
+            cgroup.attach(&mut cmd)?;
+        }
+
+        let mut backend_process = cmd.spawn()?;
+
+        // Forward the debug output of thunder with long lines truncated
+        let max_log_line = self.0.max_log_line as usize;
+        if let Some(stdout) = backend_process.stdout.take() {
+            std::thread::spawn(move || {
+                super::logging::forward(stdout, std::io::stdout(), max_log_line)
+            });
+        }
+        if let Some(stderr) = backend_process.stderr.take() {
+            std::thread::spawn(move || {
+                super::logging::forward(stderr, std::io::stderr(), max_log_line)
+            });
+        }
+
+        // Backend service PID
+        let backend_pid = backend_process.id() as i32;
+        log::info!("Thunder Backend Server PID: {backend_pid}");
+        super::pause::set_group(backend_pid);
+        super::priority::apply(backend_pid, self.0.nice, self.0.ionice);
+        Ok(backend_process)
+    }
+}
+
+/// Stop the launcher and wait for it, killing its process group when it does not exit
+/// within [`STOP_TIMEOUT`]
+fn stop(process: &mut Child) {
+    let pid = Pid::from_raw(process.id() as i32);
+    // Paused processes can not handle the termination signal
+    let _ = signal::killpg(pid, signal::SIGCONT);
+    super::pause::set_group(0);
+    if let Err(err) = signal::kill(pid, signal::SIGINT) {
+        log::error!("The backend kill error: {}", err);
+        let _ = signal::kill(pid, signal::SIGTERM);
+    }
+
+    let started = std::time::Instant::now();
+    while started.elapsed() < STOP_TIMEOUT {
+        if !matches!(process.try_wait(), Ok(None)) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    log::warn!(
+        "Thunder Backend Server did not stop within {:?}, killing it",
+        STOP_TIMEOUT
+    );
+    let _ = signal::killpg(pid, signal::SIGKILL);
+    let _ = process.wait();
+}
+
+/// Raise the soft RLIMIT_NOFILE, clamped to the hard limit
+fn raise_nofile_limit(max_open_files: u64) -> anyhow::Result<()> {
+    use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+    let (soft, hard) = getrlimit(Resource::RLIMIT_NOFILE)?;
+    let target = if max_open_files > hard {
+        log::warn!(
+            "Max open files {} exceeds the hard limit {}, clamped",
+            max_open_files,
+            hard
+        );
+        hard
+    } else {
+        max_open_files
+    };
+    setrlimit(Resource::RLIMIT_NOFILE, target, hard)?;
+    log::info!("Open files limit: {} -> {}", soft, target);
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::{
+    ffi::CString,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CGROUP_NAME: &str = "thunder";
+// cpu.max period in microseconds
+const CPU_PERIOD: u64 = 100_000;
+
+/// Thunder cgroup v2 with CPU and memory limits
+pub(super) struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create the cgroup and write the limits, `None` when no limit is configured
+    /// or cgroup v2 is unavailable
+    pub(super) fn new(
+        cpu_limit: Option<f64>,
+        memory_limit: Option<u64>,
+    ) -> anyhow::Result<Option<Self>> {
+        if cpu_limit.is_none() && memory_limit.is_none() {
+            return Ok(None);
+        }
+
+        let root = Path::new(CGROUP_ROOT);
+        if !root.join("cgroup.controllers").exists() {
+            if root.join("memory").exists() || root.join("cpu").exists() {
+                log::warn!("Only cgroup v1 is available, CPU/memory limits are ignored");
+            } else {
+                log::warn!(
+                    "cgroup v2 is not mounted at {CGROUP_ROOT}, CPU/memory limits are ignored"
+                );
+            }
+            return Ok(None);
+        }
+
+        // Enable the controllers for child cgroups
+        let mut controllers = Vec::new();
+        if cpu_limit.is_some() {
+            controllers.push("+cpu");
+        }
+        if memory_limit.is_some() {
+            controllers.push("+memory");
+        }
+        if let Err(err) = std::fs::write(root.join("cgroup.subtree_control"), controllers.join(" "))
+        {
+            log::warn!(
+                "Failed to enable cgroup controllers {:?}: {}",
+                controllers,
+                err
+            );
+        }
+
+        let path = root.join(CGROUP_NAME);
+        if !path.exists() {
+            std::fs::create_dir(&path)
+                .context(format!("Failed to create cgroup: {}", path.display()))?;
+        }
+
+        if let Some(cpu_limit) = cpu_limit {
+            let quota = (cpu_limit * CPU_PERIOD as f64) as u64;
+            std::fs::write(path.join("cpu.max"), format!("{quota} {CPU_PERIOD}"))
+                .context("Failed to set cgroup cpu.max")?;
+            log::info!("Thunder CPU limit: {cpu_limit} cores");
+        }
+
+        if let Some(memory_limit) = memory_limit {
+            std::fs::write(path.join("memory.max"), memory_limit.to_string())
+                .context("Failed to set cgroup memory.max")?;
+            log::info!("Thunder memory limit: {memory_limit} bytes");
+        }
+
+        Ok(Some(Self { path }))
+    }
+
+    /// Move the command into the cgroup before exec
+    pub(super) fn attach(&self, cmd: &mut Command) -> anyhow::Result<()> {
+        let procs = CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes())?;
+        unsafe {
+            cmd.pre_exec(move || {
+                use nix::fcntl::{open, OFlag};
+                use nix::sys::stat::Mode;
+
+                // Writing "0" moves the writing process itself
+                let fd = open(procs.as_c_str(), OFlag::O_WRONLY, Mode::empty())?;
+                let result = nix::unistd::write(fd, b"0");
+                let _ = nix::unistd::close(fd);
+                result?;
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Remove the cgroup once thunder has exited
+    pub(super) fn remove(self) {
+        if let Err(err) = std::fs::remove_dir(&self.path) {
+            log::debug!("Failed to remove cgroup {}: {}", self.path.display(), err);
+        }
+    }
+}
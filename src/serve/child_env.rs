@@ -0,0 +1,72 @@
+use std::{path::Path, sync::OnceLock};
+
+/// Parts of variable names whose values are not logged
+const SENSITIVE: [&str; 6] = ["PASSWORD", "PASSWD", "SECRET", "TOKEN", "KEY", "CREDENTIAL"];
+
+/// Extra variables, loaded once at startup
+static EXTRA: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Parse a `KEY=VALUE` environment variable
+pub(crate) fn parse(s: &str) -> anyhow::Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected KEY=VALUE"))?;
+    if key.is_empty() || key.contains(char::is_whitespace) || key.contains('\0') {
+        anyhow::bail!("Invalid environment variable name: {key:?}");
+    }
+    if value.contains('\0') {
+        anyhow::bail!("Environment variable {key} contains a NUL byte");
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+/// Load and log the extra variables merged into the environment of thunder
+pub(super) fn init(file: Option<&Path>, entries: &[(String, String)]) -> anyhow::Result<()> {
+    let envs = load(file, entries)?;
+    for (key, value) in &envs {
+        log::info!("Thunder environment: {key}={}", redact(key, value));
+    }
+    let _ = EXTRA.set(envs);
+    Ok(())
+}
+
+/// Extra variables of thunder, empty before [`init`]
+pub(super) fn extra() -> &'static [(String, String)] {
+    EXTRA.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Variables of the env file, blank lines and `#` comments skipped, followed by the
+/// `--thunder-env` ones so that they win
+pub(super) fn load(
+    file: Option<&Path>,
+    entries: &[(String, String)],
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut envs = Vec::new();
+    if let Some(file) = file {
+        let content = std::fs::read_to_string(file)
+            .map_err(|err| anyhow::anyhow!("Failed to read {}: {err}", file.display()))?;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            envs.push(
+                parse(line)
+                    .map_err(|err| anyhow::anyhow!("{}:{}: {err}", file.display(), number + 1))?,
+            );
+        }
+    }
+    envs.retain(|(key, _)| !entries.iter().any(|(k, _)| k == key));
+    envs.extend_from_slice(entries);
+    Ok(envs)
+}
+
+/// Value of the variable to log, hidden when the name looks like a secret
+pub(crate) fn redact<'a>(key: &str, value: &'a str) -> &'a str {
+    let key = key.to_ascii_uppercase();
+    match SENSITIVE.iter().any(|part| key.contains(part)) {
+        true => "<redacted>",
+        false => value,
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::{Duration, SystemTime};
+
+/// Source of time of the mount retry backoff, the OCSP refresh and the certificate
+/// expiry checks, so tests can advance time without waiting for it
+pub(super) trait Clock {
+    /// Current wall-clock time
+    fn now(&self) -> SystemTime;
+    /// Block the calling thread for `duration`
+    fn sleep(&self, duration: Duration);
+}
+
+/// The system clock
+pub(super) struct System;
+
+impl Clock for System {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// Clock that only moves when slept on, recording every sleep
+#[cfg(test)]
+pub(super) struct Fake {
+    now: std::cell::Cell<SystemTime>,
+    slept: std::cell::RefCell<Vec<Duration>>,
+}
+
+#[cfg(test)]
+impl Fake {
+    pub(super) fn at(now: SystemTime) -> Self {
+        Self {
+            now: std::cell::Cell::new(now),
+            slept: Default::default(),
+        }
+    }
+
+    pub(super) fn slept(&self) -> Vec<Duration> {
+        self.slept.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl Clock for Fake {
+    fn now(&self) -> SystemTime {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+        self.slept.borrow_mut().push(duration);
+    }
+}
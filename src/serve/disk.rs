@@ -0,0 +1,68 @@
+use std::{path::PathBuf, time::Duration};
+
+/// Interval between free space checks
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Pause thunder while the free space of the download directory is below `min_free`
+/// and resume it once space is freed, a pause not made here is left alone. The stopped
+/// launcher cannot answer the UI, which shows the maintenance page meanwhile.
+pub(super) fn watch(path: PathBuf, min_free: u64) {
+    let result = std::thread::Builder::new()
+        .name("disk".to_owned())
+        .spawn(move || {
+            // Whether the current pause and maintenance mode were made here
+            let mut paused = false;
+            let mut maintenance = false;
+            loop {
+                std::thread::sleep(CHECK_INTERVAL);
+                let free = match crate::util::free_space(&path) {
+                    Ok(free) => free,
+                    Err(err) => {
+                        log::warn!("Disk: {err}");
+                        continue;
+                    }
+                };
+                // Resumed by someone else meanwhile
+                let low = free < min_free;
+                let is_paused = super::pause::paused();
+                paused &= is_paused;
+                if maintenance && !paused && !low {
+                    maintenance = false;
+                    super::maintenance::set(false);
+                }
+                if low && !is_paused {
+                    match super::pause::pause() {
+                        Ok(count) => {
+                            paused = true;
+                            if !super::maintenance::enabled() {
+                                maintenance = true;
+                                super::maintenance::set(true);
+                            }
+                            log::warn!(
+                                "Disk: {free} bytes free under {}, paused {count} processes",
+                                path.display()
+                            );
+                        }
+                        Err(err) => log::debug!("Disk: failed to pause thunder: {err}"),
+                    }
+                } else if !low && paused {
+                    match super::pause::resume() {
+                        Ok(count) => {
+                            paused = false;
+                            if std::mem::take(&mut maintenance) {
+                                super::maintenance::set(false);
+                            }
+                            log::info!(
+                                "Disk: {free} bytes free under {}, resumed {count} processes",
+                                path.display()
+                            );
+                        }
+                        Err(err) => log::debug!("Disk: failed to resume thunder: {err}"),
+                    }
+                }
+            }
+        });
+    if let Err(err) = result {
+        log::error!("Failed to start the free space check: {err}");
+    }
+}
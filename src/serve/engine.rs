@@ -0,0 +1,95 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use nix::{sys::signal, unistd::Pid};
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::constant;
+
+/// Time allowed for thunder to be ready again after a restart
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Set while a restart requested through the API is in progress
+static RESTARTING: AtomicBool = AtomicBool::new(false);
+
+/// POST /api/v1/engine/restart handler, restarts thunder through the backend and
+/// answers once the new launcher is ready
+pub(super) async fn post_restart() -> Response {
+    if RESTARTING.swap(true, Ordering::SeqCst) {
+        return (StatusCode::CONFLICT, "A restart is already in progress").into_response();
+    }
+    // Owned by a task of its own, so a client going away does not end the restart early
+    // and let a second one start while thunder is still coming up
+    let task = tokio::spawn(async {
+        let _guard = Guard;
+        restart().await
+    });
+    match task.await {
+        Ok(response) => response,
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// Clears [`RESTARTING`] once the restart has completed or timed out
+struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        RESTARTING.store(false, Ordering::SeqCst);
+    }
+}
+
+async fn restart() -> Response {
+    let old = thunder_pid();
+    // The backend handles SIGUSR2 by restarting thunder
+    if let Err(err) = signal::raise(signal::SIGUSR2) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+
+    let started = Instant::now();
+    while started.elapsed() < READY_TIMEOUT {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        if let Some(pid) = thunder_pid().filter(|pid| Some(*pid) != old) {
+            if ready(pid).await {
+                log::info!("Thunder restarted in {:?}", started.elapsed());
+                return Json(serde_json::json!({ "pid": pid })).into_response();
+            }
+        }
+    }
+    log::warn!(
+        "Thunder was not ready within {:?} of the restart",
+        READY_TIMEOUT
+    );
+    (
+        StatusCode::GATEWAY_TIMEOUT,
+        format!(
+            "Thunder was not ready within {}s of the restart",
+            READY_TIMEOUT.as_secs()
+        ),
+    )
+        .into_response()
+}
+
+/// Pid of thunder written by the launcher
+fn thunder_pid() -> Option<i32> {
+    std::fs::read_to_string(constant::PID_FILE)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Whether thunder is alive and the launcher answers on its socket
+async fn ready(pid: i32) -> bool {
+    let sock = constant::LAUNCHER_SOCK.trim_start_matches("unix://");
+    signal::kill(Pid::from_raw(pid), None).is_ok()
+        && tokio::net::UnixStream::connect(Path::new(sock))
+            .await
+            .is_ok()
+}
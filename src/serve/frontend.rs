@@ -1,20 +1,26 @@
 use super::{
-    auth::{token, CHECK_AUTH, EXP},
+    auth::{self, token, Role},
     error::AppError,
     ext::RequestExt,
+    health,
+    proxy_protocol::{ClientAcceptor, ClientAddr},
     ConfigExt,
 };
-use crate::{constant, InstallConfig, Running, ServeConfig};
+use crate::{constant, AuthMode, InstallConfig, Running, ServeConfig};
 use anyhow::Context;
 use axum::{
     body::{Body, StreamBody},
     extract::State,
-    http::{header, HeaderName, HeaderValue, StatusCode},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
     response::{Html, IntoResponse, Redirect, Response},
     routing::{any, get, post},
     Form, Json, Router,
 };
-use axum_server::{tls_rustls::RustlsConfig, AddrIncomingConfig, Handle, HttpConfig};
+use axum_server::{
+    accept::DefaultAcceptor,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+    AddrIncomingConfig, Handle, HttpConfig,
+};
 use serde::Deserialize;
 use std::{
     io::{BufRead, Read},
@@ -28,10 +34,14 @@ use tokio_util::io::ReaderStream;
 use tower_http::trace;
 use tracing::Level;
 
+/// Time given to the in-flight requests to finish on shutdown
+pub(super) const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
 // Access cookie
-const ACCESS_COOKIE: &'static str = "access_token";
+const ACCESS_COOKIE: &str = "access_token";
 // Login html
 const LOGIN_HTML: &str = include_str!("../static/login.html");
+// Disallow every crawler
+const ROBOTS_TXT: &str = "User-agent: *\nDisallow: /\n";
 
 #[derive(Deserialize)]
 struct User {
@@ -60,32 +70,91 @@ impl FrontendServer {
         log::info!("Starting frontend server: {}", self.0.bind);
 
         // Set check auth
-        CHECK_AUTH.set(self.0.auth_password.clone())?;
+        auth::init(
+            self.0.auth_password.clone(),
+            self.0.viewer_password.clone(),
+            self.0.session_key.clone(),
+        )?;
+
+        if self.0.hsts && !self.0.tls_enabled() {
+            log::warn!("HSTS is only sent when TLS is enabled");
+        }
+
+        let state = Arc::new((self.0.clone(), self.1.clone()));
+
+        // CPU profiles and debug endpoints, behind the auth middleware
+        let router = match self.0.enable_debug_endpoints {
+            true => Router::new().route("/debug/tls", get(super::tls::get_debug_tls)),
+            false => Router::new(),
+        };
+        #[cfg(feature = "pprof")]
+        let router = match self.0.enable_pprof {
+            true => {
+                if self.0.auth_password.is_none() {
+                    log::warn!("Profiling is enabled without an authentication password");
+                }
+                router.route("/debug/pprof/profile", get(super::profile::get_profile))
+            }
+            false => router,
+        };
 
         // router
-        let router = Router::new()
+        let router = router
+            .route("/api/v1/engine/restart", post(super::engine::post_restart))
             .route("/webman/login.cgi", get(get_webman_login))
             .route("/", any(get_pan_thunder_com))
             .route("/*path", any(get_pan_thunder_com))
             // Need to auth middleware
-            .route_layer(axum::middleware::from_fn(auth_middleware))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth_middleware,
+            ))
             .route("/login", get(get_login))
             .route("/login", post(post_login))
+            .route("/logout", any(logout))
+            .route("/healthz", get(get_healthz));
+        let router = match self.0.allow_indexing {
+            true => router,
+            false => router.route("/robots.txt", get(|| async { ROBOTS_TXT })),
+        };
+        let router = router
+            .layer(axum::middleware::from_fn(super::maintenance::middleware))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                response_headers_middleware,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                request_timeout_middleware,
+            ))
             .layer(
                 tower_http::trace::TraceLayer::new_for_http()
-                    .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
+                    .make_span_with(make_span)
                     .on_response(trace::DefaultOnResponse::new().level(Level::INFO))
                     .on_request(trace::DefaultOnRequest::new().level(Level::INFO))
                     .on_failure(trace::DefaultOnFailure::new().level(Level::WARN)),
-            )
-            .with_state(Arc::new((self.0.clone(), self.1.clone())));
+            );
+        // Resolve the client address before the access log span is made
+        let router = match self.0.trusted_proxy.is_empty() {
+            true => router,
+            false => router.layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                super::real_ip::middleware,
+            )),
+        }
+        .with_state(state);
 
         // http server config
-        let http_config = HttpConfig::new()
+        let mut http_config = HttpConfig::new();
+        http_config
             .http1_title_case_headers(true)
             .http1_preserve_header_case(true)
-            .http2_keep_alive_interval(Duration::from_secs(60))
-            .build();
+            .http2_keep_alive_interval(Duration::from_secs(60));
+        // Slow clients must send their headers within the request timeout too
+        if self.0.request_timeout > 0 {
+            http_config.http1_header_read_timeout(Duration::from_secs(self.0.request_timeout));
+        }
+        let http_config = http_config.build();
 
         // http server incoming config
         let incoming_config = AddrIncomingConfig::new()
@@ -95,25 +164,60 @@ impl FrontendServer {
 
         // Signal the server to shutdown using Handle.
         let handle = Handle::new();
+        super::state::set_handle(handle.clone());
 
         // Wait for the server to shutdown gracefully
         tokio::spawn(graceful_shutdown_signal(handle.clone(), self.2));
 
-        // If tls_cert and tls_key is not None, use https
-        let result = match (self.0.tls_cert, self.0.tls_key) {
-            (Some(cert), Some(key)) => {
-                // Load tls config
-                let tls_config = RustlsConfig::from_pem_file(cert, key).await?;
+        // Log the effective settings once the address is bound
+        let listening = handle.clone();
+        let (serve_config, install_config) = (self.0.clone(), self.1.clone());
+        tokio::spawn(async move {
+            if let Some(addr) = listening.listening().await {
+                crate::daemon::mark_listening(&serve_config.pidfile, addr);
+                log_banner(addr, &serve_config, &install_config);
+            }
+        });
 
-                axum_server::bind_rustls(self.0.bind, tls_config)
+        let server = match self.0.bind_interface {
+            Some(ref name) => {
+                axum_server::from_tcp(super::listener::bind_interface(self.0.bind, name)?)
+            }
+            None => axum_server::bind(self.0.bind),
+        };
+
+        // If a certificate is configured, use https
+        let result = match super::tls::server_config(&self.0)? {
+            Some(tls_config) => {
+                // Load tls config
+                let tls_config = RustlsConfig::from_config(Arc::new(tls_config));
+
+                // A connection that stalls the handshake is dropped
+                let handshake_timeout = match self.0.handshake_timeout {
+                    0 => Duration::MAX,
+                    timeout => Duration::from_secs(timeout),
+                };
+                let acceptor = ClientAcceptor::new(
+                    super::tls::InfoAcceptor::new(
+                        RustlsAcceptor::new(tls_config).handshake_timeout(handshake_timeout),
+                    ),
+                    self.0.proxy_protocol,
+                );
+
+                server
+                    .acceptor(acceptor)
                     .handle(handle)
                     .addr_incoming_config(incoming_config)
                     .http_config(http_config)
                     .serve(router.into_make_service())
                     .await
             }
-            _ => {
-                axum_server::bind(self.0.bind)
+            None => {
+                server
+                    .acceptor(ClientAcceptor::new(
+                        DefaultAcceptor::new(),
+                        self.0.proxy_protocol,
+                    ))
                     .handle(handle)
                     .addr_incoming_config(incoming_config)
                     .http_config(http_config)
@@ -130,12 +234,30 @@ impl FrontendServer {
     }
 }
 
-/// Authentication
-fn authentication(auth_password: &str) -> bool {
-    match CHECK_AUTH.get() {
-        Some(Some(p)) => auth_password.eq(p),
-        _ => true,
-    }
+/// Summary of the effective settings, without any secret
+fn log_banner(addr: std::net::SocketAddr, conf: &ServeConfig, install_config: &InstallConfig) {
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    let auth = match (&conf.auth_password, &conf.viewer_password) {
+        (None, None) => "off",
+        (_, None) => "on",
+        (_, Some(_)) => "on, with viewer",
+    };
+    let auth_mode = match conf.auth_mode {
+        AuthMode::Form => "form",
+        AuthMode::Basic => "basic",
+    };
+    log::info!(
+        "Thunder is ready\n  listen: {}://{}\n  tls: {}\n  auth: {} ({})\n  read only: {}\n  maintenance: {}\n  download path: {}\n  config path: {}",
+        if conf.tls_enabled() { "https" } else { "http" },
+        addr,
+        on_off(conf.tls_enabled()),
+        auth,
+        auth_mode,
+        on_off(conf.read_only_ui),
+        on_off(conf.maintenance),
+        install_config.download_path.display(),
+        install_config.config_path.display(),
+    );
 }
 
 /// GET /login handler
@@ -144,14 +266,23 @@ async fn get_login() -> Html<&'static str> {
 }
 
 /// POST Login handler
-async fn post_login(user: Form<User>) -> Result<impl IntoResponse, Redirect> {
-    if authentication(user.password.as_str()) {
-        if let Ok(token) = token::generate_token() {
+async fn post_login(
+    State(conf): State<Arc<(ServeConfig, InstallConfig)>>,
+    user: Form<User>,
+) -> Result<impl IntoResponse, Redirect> {
+    if let Some(role) = auth::authentication(user.password.as_str()) {
+        let ttl = conf.0.session_ttl;
+        if let Ok(token) = token::generate_token(ttl, role) {
+            // Only send the cookie over https when tls is enabled
+            let secure = match conf.0.tls_enabled() {
+                true => "; Secure",
+                false => "",
+            };
             let resp = Response::builder()
                 .header(header::LOCATION, constant::SYNOPKG_WEB_UI_HOME)
                 .header(
                     header::SET_COOKIE,
-                    format!("{ACCESS_COOKIE}={token}; Max-Age={EXP}; Path=/; HttpOnly"),
+                    format!("{ACCESS_COOKIE}={token}; Max-Age={ttl}; Path=/; HttpOnly{secure}"),
                 )
                 .status(StatusCode::SEE_OTHER)
                 .body(Body::empty())
@@ -163,6 +294,36 @@ async fn post_login(user: Form<User>) -> Result<impl IntoResponse, Redirect> {
     Err(Redirect::to("/login"))
 }
 
+/// Logout handler, revoke the session and clear the cookie
+async fn logout<B>(request: Request<B>) -> impl IntoResponse {
+    if let Some(token) = extract_token(&request) {
+        let _ = token::revoke(token);
+    }
+    (
+        [(
+            header::SET_COOKIE,
+            format!("{ACCESS_COOKIE}=; Max-Age=0; Path=/; HttpOnly"),
+        )],
+        Redirect::to("/login"),
+    )
+}
+
+/// GET /healthz handler
+async fn get_healthz(State(conf): State<Arc<(ServeConfig, InstallConfig)>>) -> Response {
+    let timeout = Duration::from_secs(conf.0.upstream_timeout);
+    match tokio::time::timeout(timeout, health::check(&conf.0, &conf.1)).await {
+        Ok(Ok(())) => (StatusCode::OK, "OK").into_response(),
+        Ok(Err(err)) => {
+            log::warn!("Health check failed: {}", err);
+            (StatusCode::SERVICE_UNAVAILABLE, err.to_string()).into_response()
+        }
+        Err(_) => {
+            log::warn!("Upstream timeout: health check exceeded {:?}", timeout);
+            (StatusCode::GATEWAY_TIMEOUT, "Health check timed out").into_response()
+        }
+    }
+}
+
 /// GET "/webman/login.cgi" handler
 async fn get_webman_login() -> Json<&'static str> {
     Json(r#"{"SynoToken", ""}"#)
@@ -183,7 +344,7 @@ async fn get_pan_thunder_com(
     // My Server real host
     let remove_host = extract_real_host(&req);
 
-    let mut cmd = tokio::process::Command::new(constant::SYNOPKG_CLI_WEB);
+    let mut cmd = tokio::process::Command::new(conf.0.web_exe());
     cmd.current_dir(constant::SYNOPKG_PKGDEST)
         .envs(envs)
         .env("SERVER_SOFTWARE", "rust")
@@ -208,7 +369,8 @@ async fn get_pan_thunder_com(
         .uid(conf.1.uid)
         .gid(conf.1.gid)
         .stdout(Stdio::piped())
-        .stdin(Stdio::piped());
+        .stdin(Stdio::piped())
+        .kill_on_drop(true);
 
     // If debug is false, hide stderr
     if !conf.0.debug {
@@ -226,25 +388,46 @@ async fn get_pan_thunder_com(
         }
     }
 
-    req.headers.get(header::CONTENT_TYPE).map(|h| {
+    if let Some(lang) = conf.0.ui_lang {
+        cmd.env("HTTP_accept-language", lang.accept_language());
+    }
+
+    if let Some(h) = req.headers.get(header::CONTENT_TYPE) {
         cmd.env("CONTENT_TYPE", h.to_str().unwrap_or_default());
-    });
+    }
 
-    req.headers.get(header::CONTENT_LENGTH).map(|h| {
+    if let Some(h) = req.headers.get(header::CONTENT_LENGTH) {
         cmd.env("CONTENT_LENGTH", h.to_str().unwrap_or_default());
-    });
+    }
 
+    let request_uri = req.uri.to_string();
     let mut child = cmd.spawn()?;
 
-    if let Some(body) = req.body {
-        if let Some(w) = child.stdin.as_mut() {
-            let mut r = BufReader::new(&body[..]);
-            tokio::io::copy(&mut r, w).await?;
+    // The child is killed on drop when the timeout expires
+    let timeout = Duration::from_secs(conf.0.upstream_timeout);
+    let output = tokio::time::timeout(timeout, async move {
+        if let Some(body) = req.body {
+            if let Some(w) = child.stdin.as_mut() {
+                let mut r = BufReader::new(&body[..]);
+                tokio::io::copy(&mut r, w).await?;
+            }
         }
-    }
 
-    // Wait for the child to exit
-    let output = child.wait_with_output().await?;
+        // Wait for the child to exit
+        child.wait_with_output().await
+    })
+    .await;
+    let output = match output {
+        Ok(output) => output?,
+        Err(_) => {
+            log::warn!("Upstream timeout: {} exceeded {:?}", request_uri, timeout);
+            return Ok((
+                StatusCode::GATEWAY_TIMEOUT,
+                "Thunder did not respond in time",
+            )
+                .into_response());
+        }
+    };
 
     // Get status code
     let mut status_code = 200;
@@ -274,12 +457,124 @@ async fn get_pan_thunder_com(
         }
     }
 
+    // Serve byte ranges of successful GET responses
+    let position = cursor.position() as usize;
+    let mut body = cursor.into_inner().split_off(position);
+    if status_code == 200 && req.method == Method::GET {
+        if let Some(headers) = builder.headers_mut() {
+            headers
+                .entry(header::ACCEPT_RANGES)
+                .or_insert(HeaderValue::from_static("bytes"));
+        }
+        let range = req
+            .headers
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .filter(|_| if_range_matches(&req.headers, builder.headers_ref()));
+        if let Some(range) = range {
+            let len = body.len() as u64;
+            match parse_range(range, len) {
+                Some(Ok((start, end))) => {
+                    body = body[start as usize..=end as usize].to_vec();
+                    status_code = 206;
+                    if let Some(headers) = builder.headers_mut() {
+                        headers.remove(header::CONTENT_LENGTH);
+                    }
+                    builder =
+                        builder.header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}"));
+                }
+                Some(Err(())) => {
+                    return Ok((
+                        StatusCode::RANGE_NOT_SATISFIABLE,
+                        [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+                    )
+                        .into_response());
+                }
+                // Malformed and multiple ranges get the whole body
+                None => {}
+            }
+        }
+    }
+
     Ok(builder
         .status(status_code)
-        .body(StreamBody::from(ReaderStream::new(cursor)))?
+        .body(StreamBody::from(ReaderStream::new(std::io::Cursor::new(
+            body,
+        ))))?
         .into_response())
 }
 
+/// Whether the If-Range precondition, if any, matches the response validators
+fn if_range_matches(request: &HeaderMap, response: Option<&HeaderMap>) -> bool {
+    let Some(if_range) = request.get(header::IF_RANGE) else {
+        return true;
+    };
+    response.is_some_and(|headers| {
+        [header::ETAG, header::LAST_MODIFIED]
+            .iter()
+            .any(|name| headers.get(name) == Some(if_range))
+    })
+}
+
+/// Parse a single `bytes=` range into inclusive offsets. `None` means the header is
+/// ignored, `Some(Err)` that the range is not satisfiable.
+fn parse_range(range: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        // Suffix range, the last bytes
+        ("", suffix) => {
+            let suffix = suffix.parse::<u64>().ok()?;
+            // No byte of an empty body satisfies a suffix either
+            if suffix == 0 || len == 0 {
+                return Some(Err(()));
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len.saturating_sub(1)),
+        (start, end) => {
+            let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.min(len.saturating_sub(1)))
+        }
+    };
+    match start < len {
+        true => Some(Ok((start, end))),
+        false => Some(Err(())),
+    }
+}
+
+/// Access log span, with the negotiated TLS parameters in debug mode
+fn make_span<B>(request: &Request<B>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        client = tracing::field::Empty,
+        client_cn = tracing::field::Empty,
+        tls = tracing::field::Empty,
+    );
+    if let Some(ClientAddr(addr)) = request.extensions().get::<ClientAddr>() {
+        span.record("client", tracing::field::display(addr));
+    }
+    if let Some(info) = request.extensions().get::<super::tls::TlsInfo>() {
+        if let Some(cn) = info.client_cn() {
+            span.record("client_cn", tracing::field::display(cn));
+        }
+        if tracing::enabled!(Level::DEBUG) {
+            span.record("tls", tracing::field::display(info));
+        }
+    }
+    span
+}
+
 /// Extract real request host (bind, port)
 fn extract_real_host(req: &RequestExt) -> &str {
     req.headers
@@ -292,36 +587,164 @@ use axum::{http::Request, middleware::Next};
 
 /// Auth middleware
 pub(crate) async fn auth_middleware<B>(
-    request: Request<B>,
+    State(conf): State<Arc<(ServeConfig, InstallConfig)>>,
+    mut request: Request<B>,
     next: Next<B>,
-) -> Result<Response, Redirect> {
-    // If CHECK_AUTH is None, return true
-    if let Some(None) = CHECK_AUTH.get() {
-        return Ok(next.run(request).await);
-    }
-
-    // extract access_token from cookie
-    if let Some(h) = request.headers().get(header::COOKIE) {
-        let cookie = h.to_str().unwrap_or_default();
-        let cookie = cookie
-            .split(';')
-            .filter(|c| !c.is_empty())
-            .collect::<Vec<&str>>();
-        for c in cookie {
-            let c = c.trim();
-            if c.starts_with(ACCESS_COOKIE) {
-                let token = c.split('=').collect::<Vec<&str>>();
-                if token.len() == 2 {
-                    // Verify token
-                    if token::verifier(token[1]).is_ok() {
-                        return Ok(next.run(request).await);
-                    }
-                }
-            }
+) -> Result<Response, Response> {
+    // A verified client certificate, a valid session cookie or basic auth is accepted
+    let client_cn = request
+        .extensions()
+        .get::<super::tls::TlsInfo>()
+        .and_then(|info| info.client_cn())
+        .filter(|_| conf.0.tls_client_ca.is_some())
+        .map(str::to_owned);
+    let role = match (&client_cn, auth::enabled()) {
+        (Some(cn), _) if conf.0.tls_client_viewer.contains(cn) => Some(Role::Viewer),
+        (Some(_), _) | (None, false) => Some(Role::Admin),
+        (None, true) => extract_token(&request)
+            .and_then(|token| token::verifier(token).ok())
+            .or_else(|| extract_basic_password(&request).and_then(|p| auth::authentication(&p))),
+    };
+
+    if let Some(role) = role {
+        let role = match conf.0.read_only_ui {
+            true => Role::Viewer,
+            false => role,
+        };
+        let mutating = matches!(
+            *request.method(),
+            Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+        );
+        if role == Role::Viewer && mutating {
+            return Err((StatusCode::FORBIDDEN, "Read only access").into_response());
+        }
+        request.extensions_mut().insert(role);
+        if !mutating {
+            return Ok(next.run(request).await);
         }
+
+        let user = match (client_cn, auth::enabled()) {
+            (Some(cn), _) => cn,
+            (None, true) => role.as_str().to_owned(),
+            (None, false) => String::from("anonymous"),
+        };
+        let client = request
+            .extensions()
+            .get::<ClientAddr>()
+            .map(|ClientAddr(addr)| *addr);
+        let action = request.method().to_string();
+        let target = request.uri().to_string();
+        let response = next.run(request).await;
+        super::audit::record(
+            &conf.1.config_path,
+            &user,
+            client,
+            &action,
+            &target,
+            response.status().as_u16(),
+        );
+        return Ok(response);
     }
 
-    Err(Redirect::to("/login"))
+    match conf.0.auth_mode {
+        AuthMode::Form => Err(Redirect::to("/login").into_response()),
+        AuthMode::Basic => Err((
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, r#"Basic realm="Thunder""#)],
+        )
+            .into_response()),
+    }
+}
+
+/// Extract access_token from cookie
+fn extract_token<B>(request: &Request<B>) -> Option<&str> {
+    let cookie = request.headers().get(header::COOKIE)?.to_str().ok()?;
+    cookie
+        .split(';')
+        .map(|c| c.trim())
+        .filter_map(|c| c.split_once('='))
+        .find(|(k, _)| *k == ACCESS_COOKIE)
+        .map(|(_, v)| v)
+}
+
+/// Extract the password from a basic authorization header, the user name is ignored
+fn extract_basic_password<B>(request: &Request<B>) -> Option<String> {
+    use base64::Engine;
+
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    let (scheme, credentials) = authorization.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (_, password) = decoded.split_once(':')?;
+    Some(password.to_owned())
+}
+
+/// Response headers middleware, headers set by thunder itself are kept
+async fn response_headers_middleware<B>(
+    State(conf): State<Arc<(ServeConfig, InstallConfig)>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    if conf.0.hsts && conf.0.tls_enabled() {
+        headers
+            .entry(header::STRICT_TRANSPORT_SECURITY)
+            .or_insert(HeaderValue::from_static("max-age=31536000"));
+    }
+
+    if !conf.0.allow_indexing {
+        headers
+            .entry(HeaderName::from_static("x-robots-tag"))
+            .or_insert(HeaderValue::from_static("noindex, nofollow"));
+    }
+
+    for (name, value) in conf.0.response_header.iter() {
+        headers.entry(name).or_insert_with(|| value.clone());
+    }
+
+    response
+}
+
+/// Abort requests that take longer than `--request-timeout` with 408 and close the
+/// connection, except for the paths under `--request-timeout-exempt`
+async fn request_timeout_middleware<B>(
+    State(conf): State<Arc<(ServeConfig, InstallConfig)>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let exempt = conf
+        .0
+        .request_timeout_exempt
+        .iter()
+        .any(|prefix| request.uri().path().starts_with(prefix.as_str()));
+    if conf.0.request_timeout == 0 || exempt {
+        return next.run(request).await;
+    }
+    let timeout = Duration::from_secs(conf.0.request_timeout);
+    let uri = request.uri().to_string();
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            log::warn!("Request timeout: {} exceeded {:?}", uri, timeout);
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                [(header::CONNECTION, "close")],
+                "Request timed out",
+            )
+                .into_response()
+        }
+    }
 }
 
 /// Graceful shutdown signal
@@ -332,8 +755,83 @@ async fn graceful_shutdown_signal(
     tokio::select! {
         _ = graceful_shutdown.recv() => {
             println!("Received signal to shutdown");
-            handle.shutdown();
-            return ;
+            // New connections are refused from now on
+            handle.graceful_shutdown(Some(DRAIN_TIMEOUT));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_suffix() {
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        // Longer than the content, the whole content
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Ok((0, 999))));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-100", 0), Some(Err(())));
+    }
+
+    #[test]
+    fn range_open_ended() {
+        assert_eq!(parse_range("bytes=100-", 1000), Some(Ok((100, 999))));
+        assert_eq!(parse_range("bytes=0-", 1), Some(Ok((0, 0))));
+    }
+
+    #[test]
+    fn range_bounded() {
+        assert_eq!(parse_range("bytes=0-0", 1000), Some(Ok((0, 0))));
+        assert_eq!(parse_range(" bytes= 10 - 20 ", 1000), Some(Ok((10, 20))));
+        // The end is clamped to the content
+        assert_eq!(parse_range("bytes=500-5000", 1000), Some(Ok((500, 999))));
+    }
+
+    #[test]
+    fn range_start_after_end() {
+        assert_eq!(parse_range("bytes=20-10", 1000), None);
+    }
+
+    #[test]
+    fn range_start_past_length() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=1000-2000", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-", 0), Some(Err(())));
+    }
+
+    #[test]
+    fn range_multiple() {
+        assert_eq!(parse_range("bytes=0-10,20-30", 1000), None);
+        assert_eq!(parse_range("bytes=0-10, -5", 1000), None);
+    }
+
+    #[test]
+    fn range_overflow() {
+        assert_eq!(parse_range("bytes=18446744073709551616-", 1000), None);
+        assert_eq!(parse_range("bytes=0-18446744073709551616", 1000), None);
+        assert_eq!(parse_range("bytes=-18446744073709551616", 1000), None);
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 1000),
+            Some(Ok((0, 999)))
+        );
+        assert_eq!(
+            parse_range("bytes=-18446744073709551615", u64::MAX),
+            Some(Ok((0, u64::MAX - 1)))
+        );
+    }
+
+    #[test]
+    fn range_ignored() {
+        for range in [
+            "",
+            "bytes=",
+            "bytes=-",
+            "bytes=a-b",
+            "items=0-10",
+            "bytes=10",
+        ] {
+            assert_eq!(parse_range(range, 1000), None, "{range}");
         }
     }
 }
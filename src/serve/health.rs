@@ -0,0 +1,42 @@
+use nix::sys::signal;
+use nix::unistd::Pid;
+use std::path::Path;
+
+use crate::{constant, util, HealthMode, InstallConfig, ServeConfig};
+
+/// Check thunder health, the error describes why it is unhealthy
+pub(super) async fn check(
+    serve_config: &ServeConfig,
+    install_config: &InstallConfig,
+) -> anyhow::Result<()> {
+    // Liveness: the thunder process written by the launcher is alive
+    let pid = std::fs::read_to_string(constant::PID_FILE)
+        .map_err(|_| anyhow::anyhow!("Thunder is not running"))?;
+    let pid = pid.trim().parse::<i32>()?;
+    if signal::kill(Pid::from_raw(pid), None).is_err() {
+        anyhow::bail!("Thunder process {pid} is not alive");
+    }
+
+    if let HealthMode::Shallow = serve_config.health_mode {
+        return Ok(());
+    }
+
+    // Enough free space to keep downloading
+    let free = util::free_space(&install_config.download_path)?;
+    if free < serve_config.health_min_free {
+        anyhow::bail!(
+            "Free space {} bytes on {} is below {} bytes",
+            free,
+            install_config.download_path.display(),
+            serve_config.health_min_free
+        );
+    }
+
+    // The launcher answers on its control socket
+    let sock = constant::LAUNCHER_SOCK.trim_start_matches("unix://");
+    if let Err(err) = tokio::net::UnixStream::connect(Path::new(sock)).await {
+        anyhow::bail!("Thunder launcher is not responding: {err}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use anyhow::Context;
+use nix::sys::socket::{
+    bind, listen, setsockopt, socket, sockopt, AddressFamily, SockFlag, SockType, SockaddrStorage,
+};
+use std::{
+    net::{SocketAddr, TcpListener},
+    os::fd::FromRawFd,
+};
+
+/// Backlog of the listening socket
+const BACKLOG: usize = 1024;
+
+/// Check the network interface exists
+pub(super) fn check_interface(name: &str) -> anyhow::Result<()> {
+    nix::net::if_::if_nametoindex(name)
+        .map(|_| ())
+        .with_context(|| format!("Network interface {name} does not exist"))
+}
+
+/// Listen on `addr` restricted to the network interface `name` with SO_BINDTODEVICE
+pub(super) fn bind_interface(addr: SocketAddr, name: &str) -> anyhow::Result<TcpListener> {
+    check_interface(name)?;
+
+    let family = match addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+    let fd = socket(
+        family,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+        None,
+    )?;
+    // Own the socket right away so it is closed on error
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    setsockopt(fd, sockopt::ReuseAddr, &true)?;
+    setsockopt(fd, sockopt::BindToDevice, &std::ffi::OsString::from(name))
+        .with_context(|| format!("Failed to bind to network interface {name}"))?;
+    bind(fd, &SockaddrStorage::from(addr)).with_context(|| format!("Failed to bind {addr}"))?;
+    listen(fd, BACKLOG)?;
+
+    log::info!("Listening on {addr} restricted to interface {name}");
+    Ok(listener)
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, Write},
+    os::fd::AsFd,
+    sync::OnceLock,
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Stderr of the process that started thunder, kept across daemonizing
+static STDERR: OnceLock<File> = OnceLock::new();
+
+/// Keep a copy of stderr for `--log-also-stderr` before the daemon redirects it
+pub(super) fn keep_stderr() -> io::Result<()> {
+    if STDERR.get().is_none() {
+        let fd = io::stderr().as_fd().try_clone_to_owned()?;
+        let _ = STDERR.set(File::from(fd));
+    }
+    Ok(())
+}
+
+/// Log writer to stdout that truncates every line longer than `max` bytes, 0 disables it,
+/// and copies the lines to the kept stderr when `mirror` is set
+pub(super) struct Truncating {
+    pub(super) max: usize,
+    pub(super) mirror: bool,
+}
+
+impl<'a> MakeWriter<'a> for Truncating {
+    type Writer = TruncatingWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        TruncatingWriter {
+            max: self.max,
+            mirror: self.mirror.then(|| STDERR.get()).flatten(),
+        }
+    }
+}
+
+pub(super) struct TruncatingWriter {
+    max: usize,
+    mirror: Option<&'static File>,
+}
+
+impl Write for TruncatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The fmt layer writes each event at once
+        let mut stdout = io::stdout().lock();
+        for line in buf.split_inclusive(|b| *b == b'\n') {
+            let content = line.strip_suffix(b"\n").unwrap_or(line);
+            write_line(&mut stdout, content, content.len(), self.max)?;
+            if let Some(mut stderr) = self.mirror {
+                // A closed supervisor pipe must not break the log file
+                let _ = write_line(&mut stderr, content, content.len(), self.max);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Write a line and a newline, keeping at most `max` bytes followed by an elision marker.
+/// `content` may be a prefix of the line when `len`, the full length, is longer.
+fn write_line(writer: &mut impl Write, content: &[u8], len: usize, max: usize) -> io::Result<()> {
+    if max == 0 || len <= max {
+        writer.write_all(content)?;
+        return writer.write_all(b"\n");
+    }
+    // Do not cut a UTF-8 sequence
+    let mut end = max.min(content.len());
+    while end > 0 && end < content.len() && content[end] & 0xc0 == 0x80 {
+        end -= 1;
+    }
+    writer.write_all(&content[..end])?;
+    if content[..end].contains(&0x1b) {
+        writer.write_all(b"\x1b[0m")?;
+    }
+    writeln!(writer, "... [{} bytes truncated]", len - end)
+}
+
+/// Copy the output of thunder line by line to `writer`, truncating long lines without
+/// holding more than `max` bytes of a line in memory
+pub(super) fn forward(reader: impl io::Read, mut writer: impl Write, max: usize) {
+    let mut reader = io::BufReader::new(reader);
+    let mut line = Vec::new();
+    let mut len = 0;
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok([]) | Err(_) => break,
+            Ok(buf) => buf,
+        };
+        let newline = buf.iter().position(|b| *b == b'\n');
+        let chunk = &buf[..newline.unwrap_or(buf.len())];
+        // One byte more than max to find the UTF-8 boundary
+        let keep = match max {
+            0 => chunk.len(),
+            _ => (max + 1).saturating_sub(line.len()).min(chunk.len()),
+        };
+        line.extend_from_slice(&chunk[..keep]);
+        len += chunk.len();
+        let consumed = chunk.len() + usize::from(newline.is_some());
+        reader.consume(consumed);
+
+        if newline.is_some() {
+            let _ = write_line(&mut writer, &line, len, max).and_then(|_| writer.flush());
+            line.clear();
+            len = 0;
+        }
+    }
+    if len > 0 {
+        let _ = write_line(&mut writer, &line, len, max).and_then(|_| writer.flush());
+    }
+}
@@ -0,0 +1,69 @@
+use axum::{
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+/// Default maintenance page
+const MAINTENANCE_HTML: &str = include_str!("../static/maintenance.html");
+
+/// Paths that keep working in maintenance mode, the health check and the engine restart
+/// that maintenance is often turned on for
+const EXEMPT: [&str; 2] = ["/healthz", "/api/v1/engine/restart"];
+
+/// Whether the UI answers with the maintenance page
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Maintenance page served while enabled
+static PAGE: OnceLock<String> = OnceLock::new();
+
+/// Load the maintenance page and set the initial state
+pub(super) fn init(enabled: bool, page: Option<&Path>) -> anyhow::Result<()> {
+    let html = match page {
+        Some(path) => std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!("Failed to read maintenance page {}: {err}", path.display())
+        })?,
+        None => MAINTENANCE_HTML.to_owned(),
+    };
+    let _ = PAGE.set(html);
+    set(enabled);
+    Ok(())
+}
+
+/// Turn the maintenance mode on or off
+pub(super) fn set(enabled: bool) {
+    let was = ENABLED.swap(enabled, Ordering::SeqCst);
+    if was != enabled {
+        log::info!("Maintenance mode: {}", if enabled { "on" } else { "off" });
+    }
+}
+
+/// Whether the maintenance mode is on
+pub(super) fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Answer every UI request with the maintenance page and 503 while enabled, except the
+/// [`EXEMPT`] paths: `/healthz` keeps reporting the real state of thunder and the engine
+/// can still be restarted, with the authentication it always needs
+pub(super) async fn middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    if !ENABLED.load(Ordering::SeqCst) || EXEMPT.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let page = PAGE.get().map(String::as_str).unwrap_or(MAINTENANCE_HTML);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [
+            (header::RETRY_AFTER, "60"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        Html(page.to_owned()),
+    )
+        .into_response()
+}
@@ -1,26 +1,91 @@
+pub(crate) mod audit;
 mod auth;
 mod backend;
+mod cgroup;
+pub(crate) mod child_env;
+mod clock;
+mod disk;
+mod engine;
 mod error;
 mod ext;
 mod frontend;
+mod health;
+mod listener;
+mod logging;
+mod maintenance;
+#[cfg(target_os = "linux")]
+mod mount;
+mod ocsp;
+mod ownership;
+mod pause;
+pub(crate) mod priority;
+#[cfg(feature = "pprof")]
+mod profile;
+mod proxy_protocol;
+pub(crate) mod real_ip;
+pub(crate) mod selinux;
+mod sni;
+mod state;
+mod tls;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::{
-    constant,
+    constant, control,
     serve::{backend::BackendServer, frontend::FrontendServer},
     InstallConfig, Running, ServeConfig,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, io::IsTerminal};
 
 pub(crate) trait ConfigExt {
     /// Get envs
     fn envs(&self) -> anyhow::Result<HashMap<String, String>>;
 }
-pub struct Serve(ServeConfig, InstallConfig);
+pub struct Serve(ServeConfig, InstallConfig, Option<crate::daemon::Lock>);
+
+/// Check the serve config before starting, so that mistakes fail before daemonizing
+pub fn preflight(serve_config: &ServeConfig) -> anyhow::Result<()> {
+    crate::util::check_executable(&serve_config.launcher_exe())?;
+    if let Some(ref name) = serve_config.bind_interface {
+        listener::check_interface(name)?;
+    }
+    tls::check(serve_config)?;
+    if let Some(ref lower) = serve_config.overlay {
+        if !lower.is_dir() {
+            anyhow::bail!("Overlay directory {} does not exist", lower.display());
+        }
+    }
+    child_env::load(
+        serve_config.thunder_env_file.as_deref(),
+        &serve_config.thunder_env,
+    )?;
+    if let Some(ref page) = serve_config.maintenance_page {
+        std::fs::metadata(page)
+            .map_err(|err| anyhow::anyhow!("Maintenance page {}: {err}", page.display()))?;
+    }
+    Ok(())
+}
+
+/// Load the TLS materials like the server would and print their summary, `warn_days`
+/// marks certificates that expire soon
+pub fn check_tls(serve_config: &ServeConfig, warn_days: i64) -> anyhow::Result<()> {
+    tls::inspect(serve_config, warn_days)
+}
+
+/// Keep the current stderr for `--log-also-stderr`, call it before daemonizing
+pub fn keep_stderr() -> anyhow::Result<()> {
+    Ok(logging::keep_stderr()?)
+}
 
 impl Serve {
     pub fn new(serve_config: ServeConfig, install_config: InstallConfig) -> Self {
-        Self(serve_config, install_config)
+        Self(serve_config, install_config, None)
+    }
+
+    /// Hold the lifecycle lock until thunder is mounted and spawned, so that a `stop`
+    /// racing the start waits for it
+    pub(crate) fn holding(mut self, lock: crate::daemon::Lock) -> Self {
+        self.2 = Some(lock);
+        self
     }
 }
 
@@ -39,25 +104,125 @@ impl Running for Serve {
         }
 
         // Init log
+        if serve_config.log_also_stderr {
+            logging::keep_stderr()?;
+        }
         tracing_subscriber::registry()
             .with(
                 tracing_subscriber::EnvFilter::try_from_default_env()
                     .unwrap_or_else(|_| "RUST_LOG=info".into()),
             )
-            .with(tracing_subscriber::fmt::layer())
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(crate::util::use_color(std::io::stdout().is_terminal()))
+                    .with_writer(logging::Truncating {
+                        max: serve_config.max_log_line as usize,
+                        mirror: serve_config.log_also_stderr,
+                    }),
+            )
             .init();
 
-        // http server signal
+        for (path, resolved) in &install_config.resolved_symlinks {
+            log::info!(
+                "Resolved symlink {} to {}",
+                path.display(),
+                resolved.display()
+            );
+        }
+        for (source, path) in install_config.path_sources.iter().zip([
+            &install_config.config_path,
+            &install_config.download_path,
+            &install_config.mount_bind_download_path,
+        ]) {
+            match source.install_time {
+                Some(ref install_time) => log::warn!(
+                    "The {} {} from the {} is not the install-time {}, it was not prepared by install",
+                    source.name,
+                    path.display(),
+                    source.source,
+                    install_time.display()
+                ),
+                None => log::info!(
+                    "The {} is {} from the {}",
+                    source.name,
+                    path.display(),
+                    source.source
+                ),
+            }
+        }
+
+        child_env::init(
+            serve_config.thunder_env_file.as_deref(),
+            &serve_config.thunder_env,
+        )?;
+        maintenance::init(
+            serve_config.maintenance,
+            serve_config.maintenance_page.as_deref(),
+        )?;
+
+        // Control socket, shutdown is handled like a termination signal
+        let control_path = control::sock_path(&install_config.config_path);
+        control::listen(&control_path, |command| match command {
+            control::Command::Shutdown => {
+                nix::sys::signal::raise(nix::sys::signal::SIGTERM)?;
+                Ok(String::from("ok"))
+            }
+            control::Command::Pause => Ok(format!("paused {} processes", pause::pause()?)),
+            control::Command::Resume => Ok(format!("resumed {} processes", pause::resume()?)),
+            control::Command::MaintenanceOn => {
+                maintenance::set(true);
+                Ok(String::from("maintenance on"))
+            }
+            control::Command::MaintenanceOff => {
+                maintenance::set(false);
+                Ok(String::from("maintenance off"))
+            }
+            control::Command::ReloadAuth => {
+                let (Some(password), Some(session_key)) = (
+                    crate::config::stored("auth_password"),
+                    crate::config::stored("session_key"),
+                ) else {
+                    anyhow::bail!("No stored authentication password and session key")
+                };
+                auth::rotate(password, session_key);
+                Ok(String::from("authentication rotated, sessions invalidated"))
+            }
+        })?;
+
+        // Label the downloads for other confined services
+        selinux::apply(
+            install_config.download_path.clone(),
+            serve_config.selinux_context.as_deref(),
+        );
+
+        // Report or fix files under the download directory with a different owner
+        if serve_config.check_ownership || serve_config.fix_ownership {
+            ownership::watch(
+                install_config.download_path.clone(),
+                install_config.uid,
+                install_config.gid,
+                serve_config.fix_ownership,
+                std::time::Duration::from_secs(serve_config.ownership_interval),
+            );
+        }
+
+        // Pause thunder before the download filesystem fills up
+        if let Some(min_free_space) = serve_config.min_free_space {
+            disk::watch(install_config.download_path.clone(), min_free_space);
+        }
+
+        // http server signal, and the answer once it has drained
         let (tx, rx) = tokio::sync::mpsc::channel::<()>(1);
+        let (drained_tx, drained_rx) = std::sync::mpsc::channel::<()>();
 
         // Start backend thread
         let backend_thread: JoinHandle<_> = Builder::new().spawn(move || {
-            if let Some(err) = BackendServer::new(serve_config, install_config, tx)
-                .run()
-                .err()
-            {
+            let result =
+                BackendServer::new(serve_config, install_config, tx, drained_rx, self.2).run();
+            if let Err(ref err) = result {
                 log::error!("error: {}", err);
             }
+            result
         })?;
 
         // Start frontend thread
@@ -65,15 +230,18 @@ impl Running for Serve {
             if let Some(err) = FrontendServer::new(self.0, self.1, rx).run().err() {
                 log::error!("error: {}", err);
             }
+            let _ = drained_tx.send(());
         });
 
         // Wait for backend thread to finish
-        backend_thread
+        let result = backend_thread
             .join()
             .expect("Failed to join backend thread");
 
+        let _ = std::fs::remove_file(&control_path);
+
         log::info!("All services have been complete");
-        Ok(())
+        result
     }
 }
 
@@ -147,6 +315,9 @@ impl ConfigExt for (&ServeConfig, &InstallConfig) {
 
         #[cfg(all(target_os = "linux", target_env = "musl"))]
         crate::asset::libc::ld_env(&mut envs)?;
+
+        // Extra variables override the ones above
+        envs.extend(child_env::extra().iter().cloned());
         Ok(envs)
     }
 }
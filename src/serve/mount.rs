@@ -0,0 +1,334 @@
+use nix::{
+    errno::Errno,
+    mount::{MntFlags, MsFlags},
+};
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use super::clock::{self, Clock};
+
+/// Wait until `path` is a mountpoint, giving up after `timeout` unless it is zero
+pub(super) fn wait_for(path: &Path, timeout: Duration) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let mut logged = false;
+    while !is_mountpoint(path) {
+        if !timeout.is_zero() && started.elapsed() >= timeout {
+            anyhow::bail!(
+                "{} is not a mountpoint after {}s",
+                path.display(),
+                timeout.as_secs()
+            );
+        }
+        if !logged {
+            log::info!("Waiting for {} to be mounted", path.display());
+            logged = true;
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    log::info!("{} is mounted", path.display());
+    Ok(())
+}
+
+/// Whether `path` is listed as a mountpoint in /proc/self/mountinfo
+pub(super) fn is_mountpoint(path: &Path) -> bool {
+    let Ok(path) = std::fs::canonicalize(path) else {
+        return false;
+    };
+    let Ok(mountinfo) = std::fs::read_to_string("/proc/self/mountinfo") else {
+        return false;
+    };
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .any(|mountpoint| Path::new(&unescape(mountpoint)) == path)
+}
+
+/// Decode the octal escapes of mountinfo, such as `\040` for a space
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).filter(|_| bytes[i] == b'\\');
+        match escape
+            .and_then(|oct| std::str::from_utf8(oct).ok())
+            .and_then(|oct| u8::from_str_radix(oct, 8).ok())
+        {
+            Some(byte) => {
+                out.push(byte);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Bind mount `source` to `target`, retrying with doubling delay while the target is
+/// busy or not ready yet
+pub(super) fn bind(
+    source: &Path,
+    target: &Path,
+    retries: u32,
+    delay: Duration,
+) -> anyhow::Result<()> {
+    retry(&clock::System, source, target, retries, delay, || {
+        nix::mount::mount(
+            Some(source),
+            target,
+            <Option<&'static [u8]>>::None,
+            MsFlags::MS_BIND,
+            <Option<&'static [u8]>>::None,
+        )
+    })
+}
+
+/// Mount an overlay of the read-only `lower` and the writable `upper` directory on
+/// `target`, new files go to `upper`. `work` must be on the filesystem of `upper`.
+pub(super) fn overlay(
+    lower: &Path,
+    upper: &Path,
+    work: &Path,
+    target: &Path,
+    retries: u32,
+    delay: Duration,
+) -> anyhow::Result<()> {
+    for path in [lower, upper, work] {
+        let path = path.to_string_lossy();
+        // Separators of the overlay mount options
+        if path.contains([',', ':']) {
+            anyhow::bail!("Overlay directory {path} must not contain ',' or ':'");
+        }
+    }
+    if !work.exists() {
+        crate::util::create_dir_all(work, 0o700)?;
+    }
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lower.display(),
+        upper.display(),
+        work.display()
+    );
+    retry(&clock::System, upper, target, retries, delay, || {
+        nix::mount::mount(
+            Some("overlay"),
+            target,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(options.as_str()),
+        )
+    })?;
+    log::info!(
+        "Overlay of {} over {} mounted on {}",
+        upper.display(),
+        lower.display(),
+        target.display()
+    );
+    Ok(())
+}
+
+/// Unmount `target`, retrying with doubling delay while it is busy and detaching it
+/// lazily once the retries are exhausted, other errors are returned right away
+pub(super) fn unmount(target: &Path, retries: u32, delay: Duration) -> anyhow::Result<()> {
+    unmount_with(&clock::System, target, retries, delay, || {
+        nix::mount::umount(target)
+    })
+}
+
+fn unmount_with(
+    clock: &impl Clock,
+    target: &Path,
+    retries: u32,
+    delay: Duration,
+    umount: impl Fn() -> nix::Result<()>,
+) -> anyhow::Result<()> {
+    let mut delay = delay;
+    let mut attempt = 0;
+    loop {
+        match umount() {
+            Ok(()) => {
+                log::info!("Unmounted {}", target.display());
+                return Ok(());
+            }
+            Err(errno) if errno != Errno::EBUSY => {
+                anyhow::bail!("Unmount {} failed: {}", target.display(), errno)
+            }
+            Err(errno) if attempt < retries => {
+                attempt += 1;
+                log::warn!(
+                    "Unmount {} failed: {}, retry {}/{} in {:?}",
+                    target.display(),
+                    errno,
+                    attempt,
+                    retries,
+                    delay
+                );
+                clock.sleep(delay);
+                delay *= 2;
+            }
+            Err(errno) => {
+                log::warn!(
+                    "Unmount {} failed: {}, detaching it lazily",
+                    target.display(),
+                    errno
+                );
+                nix::mount::umount2(target, MntFlags::MNT_DETACH).map_err(|errno| {
+                    anyhow::anyhow!("Lazy unmount {} failed: {}", target.display(), errno)
+                })?;
+                log::info!(
+                    "Detached {}, it is unmounted once no longer busy",
+                    target.display()
+                );
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Run `mount` until it succeeds, at most `retries` more times with doubling delay
+fn retry(
+    clock: &impl Clock,
+    source: &Path,
+    target: &Path,
+    retries: u32,
+    delay: Duration,
+    mount: impl Fn() -> nix::Result<()>,
+) -> anyhow::Result<()> {
+    let mut delay = delay;
+    let mut attempt = 0;
+    loop {
+        match mount() {
+            Ok(()) => return Ok(()),
+            Err(errno) if attempt < retries => {
+                attempt += 1;
+                log::warn!(
+                    "Mount {} to {} failed: {}, retry {}/{} in {:?}",
+                    source.display(),
+                    target.display(),
+                    errno,
+                    attempt,
+                    retries,
+                    delay
+                );
+                clock.sleep(delay);
+                delay *= 2;
+            }
+            Err(errno) => anyhow::bail!(
+                "Mount {} to {} failed: {}",
+                source.display(),
+                target.display(),
+                errno
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, time::UNIX_EPOCH};
+
+    /// Mount operation failing with EBUSY the first `failures` times
+    fn busy(failures: u32, calls: &Cell<u32>) -> impl Fn() -> nix::Result<()> + '_ {
+        move || {
+            calls.set(calls.get() + 1);
+            match calls.get() > failures {
+                true => Ok(()),
+                false => Err(nix::errno::Errno::EBUSY),
+            }
+        }
+    }
+
+    #[test]
+    fn retry_doubles_the_delay() {
+        let clock = clock::Fake::at(UNIX_EPOCH);
+        let calls = Cell::new(0);
+        let (source, target) = (Path::new("/a"), Path::new("/b"));
+        retry(
+            &clock,
+            source,
+            target,
+            5,
+            Duration::from_secs(1),
+            busy(3, &calls),
+        )
+        .unwrap();
+        assert_eq!(calls.get(), 4);
+        assert_eq!(clock.slept(), [1, 2, 4].map(Duration::from_secs).to_vec());
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(7));
+    }
+
+    #[test]
+    fn retry_gives_up() {
+        let clock = clock::Fake::at(UNIX_EPOCH);
+        let calls = Cell::new(0);
+        let (source, target) = (Path::new("/a"), Path::new("/b"));
+        let err = retry(
+            &clock,
+            source,
+            target,
+            2,
+            Duration::from_secs(3),
+            busy(10, &calls),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("EBUSY"), "{err}");
+        assert_eq!(calls.get(), 3);
+        assert_eq!(clock.slept(), [3, 6].map(Duration::from_secs).to_vec());
+    }
+
+    #[test]
+    fn retry_without_retries() {
+        let clock = clock::Fake::at(UNIX_EPOCH);
+        let calls = Cell::new(0);
+        let (source, target) = (Path::new("/a"), Path::new("/b"));
+        assert!(retry(
+            &clock,
+            source,
+            target,
+            0,
+            Duration::from_secs(1),
+            busy(1, &calls)
+        )
+        .is_err());
+        assert!(clock.slept().is_empty());
+    }
+
+    #[test]
+    fn unmount_retries_until_free() {
+        let clock = clock::Fake::at(UNIX_EPOCH);
+        let calls = Cell::new(0);
+        unmount_with(
+            &clock,
+            Path::new("/b"),
+            3,
+            Duration::from_millis(500),
+            busy(2, &calls),
+        )
+        .unwrap();
+        assert_eq!(calls.get(), 3);
+        assert_eq!(
+            clock.slept(),
+            [500, 1000].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn unmount_fails_fast_when_not_busy() {
+        let clock = clock::Fake::at(UNIX_EPOCH);
+        let calls = Cell::new(0);
+        let err = unmount_with(&clock, Path::new("/b"), 3, Duration::from_secs(1), || {
+            calls.set(calls.get() + 1);
+            Err(nix::errno::Errno::EINVAL)
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("EINVAL"), "{err}");
+        assert_eq!(calls.get(), 1);
+        assert!(clock.slept().is_empty());
+    }
+}
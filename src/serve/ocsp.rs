@@ -0,0 +1,380 @@
+use anyhow::Context;
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate, PrivateKey,
+};
+use std::{
+    io::Read,
+    sync::{Arc, RwLock},
+    time::{Duration, UNIX_EPOCH},
+};
+use x509_parser::{
+    extensions::{GeneralName, ParsedExtension},
+    oid_registry::OID_PKIX_ACCESS_DESCRIPTOR_OCSP,
+    prelude::{FromDer, X509Certificate},
+};
+
+use super::clock::{self, Clock};
+
+/// Refresh interval when the response has no next update time
+const DEFAULT_REFRESH: Duration = Duration::from_secs(12 * 60 * 60);
+/// Retry interval after a failed fetch
+const RETRY: Duration = Duration::from_secs(5 * 60);
+/// Timeout of a request to the OCSP responder
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate resolver that staples the latest OCSP response
+pub(super) struct StapledCert(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for StapledCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.0.read().ok().map(|key| key.clone())
+    }
+}
+
+/// Build the stapling resolver and refresh the OCSP response on a background thread.
+/// Until a response is fetched, handshakes go on without stapling.
+pub(super) fn resolver(
+    certs: Vec<Certificate>,
+    key: &PrivateKey,
+) -> anyhow::Result<Arc<StapledCert>> {
+    let signing_key = rustls::sign::any_supported_type(key)
+        .map_err(|_| anyhow::anyhow!("Unsupported private key"))?;
+    let resolver = Arc::new(StapledCert(RwLock::new(Arc::new(CertifiedKey::new(
+        certs.clone(),
+        signing_key.clone(),
+    )))));
+
+    let stapled = resolver.clone();
+    std::thread::Builder::new()
+        .name("ocsp".to_owned())
+        .spawn(move || loop {
+            let wait = match fetch(&certs) {
+                Ok((response, refresh)) => {
+                    let mut key = CertifiedKey::new(certs.clone(), signing_key.clone());
+                    key.ocsp = Some(response);
+                    if let Ok(mut current) = stapled.0.write() {
+                        *current = Arc::new(key);
+                    }
+                    log::info!("OCSP response stapled, next refresh in {:?}", refresh);
+                    refresh
+                }
+                Err(err) => {
+                    log::warn!("OCSP fetch failed, serving without stapling: {:#}", err);
+                    RETRY
+                }
+            };
+            std::thread::sleep(wait)
+        })?;
+    Ok(resolver)
+}
+
+/// Fetch the OCSP response of the leaf certificate, returns the DER response and when to
+/// refresh it
+fn fetch(certs: &[Certificate]) -> anyhow::Result<(Vec<u8>, Duration)> {
+    let leaf = parse(&certs[0])?;
+    let issuer = parse(
+        certs
+            .get(1)
+            .context("OCSP stapling needs the issuer certificate in the chain")?,
+    )?;
+
+    let url = leaf
+        .extensions()
+        .iter()
+        .find_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => aia
+                .iter()
+                .filter(|desc| desc.access_method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP)
+                .find_map(|desc| match desc.access_location {
+                    GeneralName::URI(uri) => Some(uri.to_owned()),
+                    _ => None,
+                }),
+            _ => None,
+        })
+        .context("The certificate has no OCSP responder")?;
+
+    let response = ureq::post(&url)
+        .timeout(TIMEOUT)
+        .set("Content-Type", "application/ocsp-request")
+        .send_bytes(&request(&leaf, &issuer))?;
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(1 << 20)
+        .read_to_end(&mut body)?;
+
+    let next_update = parse_response(&body)?;
+    Ok((body, refresh(&clock::System, next_update)?))
+}
+
+/// When to refresh a response with the given next update time, in seconds since the epoch
+fn refresh(clock: &impl Clock, next_update: Option<u64>) -> anyhow::Result<Duration> {
+    let now = clock.now().duration_since(UNIX_EPOCH)?.as_secs();
+    Ok(match next_update {
+        // Refresh halfway to the next update
+        Some(next) if next > now => Duration::from_secs(((next - now) / 2).max(60)),
+        Some(_) => RETRY,
+        None => DEFAULT_REFRESH,
+    })
+}
+
+fn parse(cert: &Certificate) -> anyhow::Result<X509Certificate<'_>> {
+    X509Certificate::from_der(&cert.0)
+        .map(|(_, cert)| cert)
+        .map_err(|err| anyhow::anyhow!("Invalid certificate: {err}"))
+}
+
+/// DER encoded OCSPRequest for a single certificate, identified with SHA-1 hashes
+fn request(leaf: &X509Certificate, issuer: &X509Certificate) -> Vec<u8> {
+    use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY as SHA1};
+
+    let name_hash = digest(&SHA1, leaf.issuer().as_raw());
+    let key_hash = digest(&SHA1, &issuer.public_key().subject_public_key.data);
+    // AlgorithmIdentifier { id-sha1, NULL }
+    let algorithm = [
+        0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00,
+    ];
+
+    let cert_id = tlv(
+        0x30,
+        &[
+            algorithm.to_vec(),
+            tlv(0x04, name_hash.as_ref()),
+            tlv(0x04, key_hash.as_ref()),
+            tlv(0x02, leaf.raw_serial()),
+        ]
+        .concat(),
+    );
+    // OCSPRequest { TBSRequest { requestList { Request { CertID } } } }
+    tlv(0x30, &tlv(0x30, &tlv(0x30, &tlv(0x30, &cert_id))))
+}
+
+/// Encode a DER tag, length and value
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let len = value.len();
+    let mut out = vec![tag];
+    match len {
+        0..=0x7f => out.push(len as u8),
+        0x80..=0xff => out.extend([0x81, len as u8]),
+        _ => out.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+/// Decode a DER tag, value and the remaining input
+fn read_tlv(input: &[u8]) -> anyhow::Result<(u8, &[u8], &[u8])> {
+    let invalid = || anyhow::anyhow!("Invalid OCSP response");
+    let (&tag, rest) = input.split_first().ok_or_else(invalid)?;
+    let (&first, rest) = rest.split_first().ok_or_else(invalid)?;
+    let (len, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81..=0x84 => {
+            let n = (first & 0x7f) as usize;
+            let bytes = rest.get(..n).ok_or_else(invalid)?;
+            let len = bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+            (len, &rest[n..])
+        }
+        _ => return Err(invalid()),
+    };
+    let value = rest.get(..len).ok_or_else(invalid)?;
+    Ok((tag, value, &rest[len..]))
+}
+
+/// Check the OCSP response and return the next update time of the first single
+/// response, in seconds since the epoch
+fn parse_response(der: &[u8]) -> anyhow::Result<Option<u64>> {
+    // OCSPResponse { responseStatus, [0] { ResponseBytes { responseType, response } } }
+    let (_, response, _) = read_tlv(der)?;
+    let (_, status, rest) = read_tlv(response)?;
+    if status != [0] {
+        anyhow::bail!("OCSP responder returned status {:?}", status);
+    }
+    let (_, bytes, _) = read_tlv(rest)?;
+    let (_, bytes, _) = read_tlv(bytes)?;
+    let (_, _, rest) = read_tlv(bytes)?;
+    let (_, basic, _) = read_tlv(rest)?;
+
+    // BasicOCSPResponse { ResponseData { [0] version?, responderID, producedAt, responses } }
+    let (_, basic, _) = read_tlv(basic)?;
+    let (_, mut data, _) = read_tlv(basic)?;
+    let responses = loop {
+        let (tag, value, rest) = read_tlv(data)?;
+        if tag == 0x30 {
+            break value;
+        }
+        data = rest;
+    };
+
+    // SingleResponse { certID, certStatus, thisUpdate, [0] nextUpdate? }
+    let (_, single, _) = read_tlv(responses)?;
+    let (_, _, rest) = read_tlv(single)?;
+    let (status, _, rest) = read_tlv(rest)?;
+    if status == 0xa1 {
+        log::warn!("OCSP responder reports the certificate as revoked");
+    }
+    let (_, _, rest) = read_tlv(rest)?;
+    match read_tlv(rest) {
+        Ok((0xa0, next, _)) => {
+            let (_, time, _) = read_tlv(next)?;
+            Ok(Some(generalized_time(time)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Parse a GeneralizedTime "YYYYMMDDHHMMSSZ" into seconds since the epoch
+fn generalized_time(time: &[u8]) -> anyhow::Result<u64> {
+    let s = std::str::from_utf8(time)?;
+    let field = |range: std::ops::Range<usize>| -> anyhow::Result<i64> {
+        s.get(range)
+            .and_then(|v| v.parse().ok())
+            .context("Invalid OCSP time")
+    };
+    let (y, m, d) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    // Days from civil, Howard Hinnant's algorithm
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + field(8..10)? * 3600 + field(10..12)? * 60 + field(12..14)?;
+    Ok(secs.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60;
+    /// Response for a good certificate with a next update, from `openssl ocsp -ndays 7`
+    const GOOD: &[u8] = include_bytes!("testdata/ocsp_good.der");
+    /// Response for a revoked certificate without a next update
+    const REVOKED: &[u8] = include_bytes!("testdata/ocsp_revoked.der");
+
+    #[test]
+    fn refresh_halfway_to_next_update() {
+        let clock = clock::Fake::at(UNIX_EPOCH + Duration::from_secs(1000 * HOUR));
+        let next = 1000 * HOUR + 24 * HOUR;
+        assert_eq!(
+            refresh(&clock, Some(next)).unwrap(),
+            Duration::from_secs(12 * HOUR)
+        );
+
+        // At least a minute however close the next update is
+        clock.sleep(Duration::from_secs(24 * HOUR - 10));
+        assert_eq!(
+            refresh(&clock, Some(next)).unwrap(),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn refresh_past_next_update() {
+        let clock = clock::Fake::at(UNIX_EPOCH + Duration::from_secs(1000 * HOUR));
+        assert_eq!(refresh(&clock, Some(1000 * HOUR)).unwrap(), RETRY);
+        assert_eq!(refresh(&clock, Some(0)).unwrap(), RETRY);
+    }
+
+    #[test]
+    fn refresh_without_next_update() {
+        let clock = clock::Fake::at(UNIX_EPOCH);
+        assert_eq!(refresh(&clock, None).unwrap(), DEFAULT_REFRESH);
+    }
+
+    #[test]
+    fn parse_good() {
+        // Next Update: Oct 21 09:51:00 2026 GMT
+        assert_eq!(parse_response(GOOD).unwrap(), Some(1792576260));
+    }
+
+    #[test]
+    fn parse_revoked() {
+        assert_eq!(parse_response(REVOKED).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_unsuccessful() {
+        // OCSPResponse { responseStatus unauthorized(6) }
+        let err = parse_response(&[0x30, 0x03, 0x0a, 0x01, 0x06]).unwrap_err();
+        assert_eq!(err.to_string(), "OCSP responder returned status [6]");
+    }
+
+    #[test]
+    fn parse_truncated() {
+        for len in 0..GOOD.len() {
+            assert!(parse_response(&GOOD[..len]).is_err(), "length {len}");
+        }
+    }
+
+    #[test]
+    fn parse_malformed() {
+        // Must not panic whatever a corrupted byte turns into
+        for i in 0..GOOD.len() {
+            for byte in [0x00, 0x7f, 0x80, 0x84, 0xff] {
+                let mut der = GOOD.to_vec();
+                der[i] = byte;
+                let _ = parse_response(&der);
+            }
+        }
+        for der in [
+            &[][..],
+            &[0x30],
+            &[0x30, 0x80],
+            &[0x30, 0x85, 0, 0, 0, 0, 1],
+        ] {
+            assert!(parse_response(der).is_err());
+        }
+    }
+
+    #[test]
+    fn read_tlv_lengths() {
+        for len in [0, 0x7f, 0x80, 0xff, 0x100, 0x1234] {
+            let value = vec![0xab; len];
+            let der = [tlv(0x04, &value), vec![0x05, 0x00]].concat();
+            let (tag, read, rest) = read_tlv(&der).unwrap();
+            assert_eq!((tag, read, rest), (0x04, &value[..], &[0x05, 0x00][..]));
+        }
+    }
+
+    #[test]
+    fn read_tlv_invalid() {
+        for der in [
+            &[][..],
+            &[0x04],
+            &[0x04, 0x02, 0x00],
+            &[0x04, 0x80],
+            &[0x04, 0x85, 0, 0, 0, 0, 0],
+            &[0x04, 0x82, 0x01],
+            &[0x04, 0x84, 0xff, 0xff, 0xff, 0xff],
+        ] {
+            assert!(read_tlv(der).is_err(), "{der:02x?}");
+        }
+    }
+
+    #[test]
+    fn generalized_time_valid() {
+        assert_eq!(generalized_time(b"19700101000000Z").unwrap(), 0);
+        assert_eq!(generalized_time(b"20261021095100Z").unwrap(), 1792576260);
+        assert_eq!(generalized_time(b"20240229120000Z").unwrap(), 1709208000);
+        // Before the epoch is clamped
+        assert_eq!(generalized_time(b"19691231235959Z").unwrap(), 0);
+    }
+
+    #[test]
+    fn generalized_time_invalid() {
+        for time in [
+            &b""[..],
+            b"2026",
+            b"2026102109510",
+            b"2026-10-21T09:51:00Z",
+            b"20261021O95100Z",
+            &[0xff; 15],
+        ] {
+            assert!(generalized_time(time).is_err(), "{time:?}");
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Ownership scan result
+#[derive(Default)]
+struct Drift {
+    scanned: u64,
+    drifted: u64,
+    fixed: u64,
+}
+
+/// Check the ownership of the download directory now and then every `interval`,
+/// chown the drifted entries when `fix` is set
+pub(super) fn watch(path: PathBuf, uid: u32, gid: u32, fix: bool, interval: Duration) {
+    let result = std::thread::Builder::new()
+        .name("ownership".to_owned())
+        .spawn(move || loop {
+            let mut drift = Drift::default();
+            scan(&path, uid, gid, fix, &mut drift);
+            match (drift.drifted, fix) {
+                (0, _) => log::info!(
+                    "Ownership: all {} entries under {} are owned by {uid}:{gid}",
+                    drift.scanned,
+                    path.display()
+                ),
+                (_, true) => log::warn!(
+                    "Ownership: {} of {} entries under {} were not owned by {uid}:{gid}, fixed {}",
+                    drift.drifted,
+                    drift.scanned,
+                    path.display(),
+                    drift.fixed
+                ),
+                (_, false) => log::warn!(
+                    "Ownership: {} of {} entries under {} are not owned by {uid}:{gid}",
+                    drift.drifted,
+                    drift.scanned,
+                    path.display()
+                ),
+            }
+            std::thread::sleep(interval)
+        });
+    if let Err(err) = result {
+        log::error!("Failed to start the ownership check: {err}");
+    }
+}
+
+/// Walk the directory without following symlinks
+fn scan(dir: &Path, uid: u32, gid: u32, fix: bool, drift: &mut Drift) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        drift.scanned += 1;
+        if metadata.uid() != uid || metadata.gid() != gid {
+            drift.drifted += 1;
+            log::debug!(
+                "Ownership: {} is owned by {}:{}",
+                path.display(),
+                metadata.uid(),
+                metadata.gid()
+            );
+            if fix {
+                match fchownat(
+                    None,
+                    &path,
+                    Some(Uid::from_raw(uid)),
+                    Some(Gid::from_raw(gid)),
+                    FchownatFlags::NoFollowSymlink,
+                ) {
+                    Ok(()) => drift.fixed += 1,
+                    Err(err) => log::warn!("Failed to chown {}: {}", path.display(), err),
+                }
+            }
+        }
+        if metadata.is_dir() {
+            scan(&path, uid, gid, fix, drift);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+
+/// Process group of the thunder launcher, 0 if not running
+static GROUP: AtomicI32 = AtomicI32::new(0);
+
+/// Record the process group of the spawned launcher
+pub(super) fn set_group(pgid: i32) {
+    GROUP.store(pgid, Ordering::SeqCst);
+}
+
+/// Stop every thunder process, returns the number of processes affected
+pub(super) fn pause() -> anyhow::Result<usize> {
+    signal_group(Signal::SIGSTOP)
+}
+
+/// Continue every thunder process, returns the number of processes affected
+pub(super) fn resume() -> anyhow::Result<usize> {
+    signal_group(Signal::SIGCONT)
+}
+
+/// Number of thunder processes, 0 if not running
+pub(super) fn members() -> usize {
+    match GROUP.load(Ordering::SeqCst) {
+        0 => 0,
+        pgid => group_members(pgid),
+    }
+}
+
+/// Whether the thunder processes are stopped, by this server or anyone else
+pub(super) fn paused() -> bool {
+    match GROUP.load(Ordering::SeqCst) {
+        0 => false,
+        pgid => group_states(pgid).any(|state| state == 'T'),
+    }
+}
+
+fn signal_group(sig: Signal) -> anyhow::Result<usize> {
+    let pgid = GROUP.load(Ordering::SeqCst);
+    if pgid == 0 {
+        anyhow::bail!("Thunder backend is not running")
+    }
+    let count = group_members(pgid);
+    signal::killpg(Pid::from_raw(pgid), sig)?;
+    Ok(count)
+}
+
+/// Count the processes of a process group from /proc
+fn group_members(pgid: i32) -> usize {
+    group_states(pgid).count()
+}
+
+/// States of the processes of a process group from /proc
+fn group_states(pgid: i32) -> impl Iterator<Item = char> {
+    std::fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("stat")).ok())
+        .filter_map(move |stat| {
+            // Fields after the command name: state, ppid, pgrp
+            let (_, rest) = stat.rsplit_once(')')?;
+            let mut fields = rest.split_whitespace();
+            let state = fields.next()?.chars().next()?;
+            (fields.nth(1)?.parse::<i32>().ok()? == pgid).then_some(state)
+        })
+}
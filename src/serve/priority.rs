@@ -0,0 +1,89 @@
+use nix::libc;
+
+/// `IOPRIO_WHO_PGRP` of ioprio_set(2)
+const IOPRIO_WHO_PGRP: libc::c_int = 2;
+/// Shift of the class in an IO priority value
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+/// IO scheduling class and level of `--ionice`
+#[derive(Clone, Copy)]
+pub(crate) struct IoNice {
+    class: IoClass,
+    level: u8,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum IoClass {
+    Realtime = 1,
+    BestEffort = 2,
+    Idle = 3,
+}
+
+/// Parse "idle", "best-effort[:0-7]" or "realtime[:0-7]"
+pub(crate) fn parse_ionice(s: &str) -> anyhow::Result<IoNice> {
+    let (class, level) = match s.split_once(':') {
+        Some((class, level)) => (class, Some(level)),
+        None => (s, None),
+    };
+    let class = match class {
+        "realtime" => IoClass::Realtime,
+        "best-effort" => IoClass::BestEffort,
+        "idle" => IoClass::Idle,
+        _ => anyhow::bail!("IO class must be one of realtime, best-effort, idle"),
+    };
+    let level = match (class, level) {
+        (IoClass::Idle, Some(_)) => anyhow::bail!("The idle IO class has no level"),
+        (_, Some(level)) => match level.parse::<u8>() {
+            Ok(level @ 0..=7) => level,
+            _ => anyhow::bail!("IO level must be between 0 and 7"),
+        },
+        (_, None) => 4,
+    };
+    Ok(IoNice { class, level })
+}
+
+/// Apply the CPU niceness and IO priority to the thunder process group
+pub(super) fn apply(pgid: i32, nice: Option<i32>, ionice: Option<IoNice>) {
+    let root = nix::unistd::geteuid().is_root();
+
+    if let Some(nice) = nice {
+        if nice < 0 && !root {
+            log::warn!("A negative nice value needs root or CAP_SYS_NICE");
+        }
+        // SAFETY: setpriority only reads its arguments
+        match unsafe { libc::setpriority(libc::PRIO_PGRP, pgid as libc::id_t, nice) } {
+            0 => log::info!("Thunder nice: {nice}"),
+            _ => log::warn!(
+                "Failed to set the thunder nice value {nice}: {}",
+                std::io::Error::last_os_error()
+            ),
+        }
+    }
+
+    if let Some(ionice) = ionice {
+        if ionice.class == IoClass::Realtime && !root {
+            log::warn!("The realtime IO class needs root or CAP_SYS_ADMIN");
+        }
+        let value =
+            ((ionice.class as libc::c_int) << IOPRIO_CLASS_SHIFT) | ionice.level as libc::c_int;
+        // SAFETY: ioprio_set only reads its arguments
+        match unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PGRP, pgid, value) } {
+            0 => log::info!("Thunder IO priority: {}", ionice),
+            _ => log::warn!(
+                "Failed to set the thunder IO priority {}: {}",
+                ionice,
+                std::io::Error::last_os_error()
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for IoNice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.class {
+            IoClass::Realtime => write!(f, "realtime:{}", self.level),
+            IoClass::BestEffort => write!(f, "best-effort:{}", self.level),
+            IoClass::Idle => f.write_str("idle"),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use super::{auth::Role, error::AppError};
+use axum::{
+    extract::Query,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Longest CPU profile that can be requested
+const MAX_SECONDS: u64 = 300;
+
+#[derive(Deserialize)]
+pub(super) struct ProfileQuery {
+    seconds: Option<u64>,
+}
+
+/// GET /debug/pprof/profile handler, samples the CPU of the serve process for
+/// `seconds` (default 30) and answers a flamegraph SVG
+pub(super) async fn get_profile(
+    Extension(role): Extension<Role>,
+    Query(query): Query<ProfileQuery>,
+) -> Result<Response, AppError> {
+    if role != Role::Admin {
+        return Ok((StatusCode::FORBIDDEN, "Profiling needs admin access").into_response());
+    }
+    let seconds = query.seconds.unwrap_or(30);
+    if !(1..=MAX_SECONDS).contains(&seconds) {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            format!("seconds must be between 1 and {MAX_SECONDS}"),
+        )
+            .into_response());
+    }
+
+    log::info!("CPU profiling for {seconds}s");
+    // The profiler guard is not Send, keep it off the async workers
+    let svg = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<u8>> {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(99)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .map_err(|err| anyhow::anyhow!("Failed to start the profiler: {err}"))?;
+        std::thread::sleep(Duration::from_secs(seconds));
+        let report = guard.report().build()?;
+        let mut svg = Vec::new();
+        report.flamegraph(&mut svg)?;
+        Ok(svg)
+    })
+    .await??;
+
+    Ok(([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response())
+}
@@ -0,0 +1,290 @@
+use axum::Extension;
+use axum_server::accept::Accept;
+use hyper::server::conn::AddrStream;
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tower_layer::Layer;
+
+/// Signature of a PROXY protocol v2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest PROXY protocol v1 header, including the CRLF
+const V1_MAX_LEN: usize = 107;
+/// Time allowed for the client to send the PROXY protocol header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Address of the client, as conveyed by the PROXY protocol header or the peer address
+#[derive(Clone, Copy)]
+pub(super) struct ClientAddr(pub(super) SocketAddr);
+
+/// Acceptor that adds the [`ClientAddr`] to the requests of each connection, reading
+/// it from the PROXY protocol header first when enabled
+#[derive(Clone)]
+pub(super) struct ClientAcceptor<A> {
+    inner: A,
+    proxy_protocol: bool,
+}
+
+impl<A> ClientAcceptor<A> {
+    pub(super) fn new(inner: A, proxy_protocol: bool) -> Self {
+        Self {
+            inner,
+            proxy_protocol,
+        }
+    }
+}
+
+impl<A, S> Accept<AddrStream, S> for ClientAcceptor<A>
+where
+    A: Accept<AddrStream, S> + Clone + Send + 'static,
+    A::Future: Send,
+    S: Send + 'static,
+{
+    type Stream = A::Stream;
+    type Service = <Extension<ClientAddr> as Layer<A::Service>>::Service;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, mut stream: AddrStream, service: S) -> Self::Future {
+        let inner = self.inner.clone();
+        let proxy_protocol = self.proxy_protocol;
+        Box::pin(async move {
+            let peer = stream.remote_addr();
+            let addr = match proxy_protocol {
+                true => {
+                    match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await {
+                        Ok(Ok(addr)) => addr.unwrap_or(peer),
+                        Ok(Err(err)) => {
+                            log::warn!("Rejected connection from {peer}: {err}");
+                            return Err(err);
+                        }
+                        Err(_) => {
+                            log::warn!("Rejected connection from {peer}: no PROXY protocol header");
+                            return Err(io::ErrorKind::TimedOut.into());
+                        }
+                    }
+                }
+                false => peer,
+            };
+            let (stream, service) = inner.accept(stream, service).await?;
+            Ok((stream, Extension(ClientAddr(addr)).layer(service)))
+        })
+    }
+}
+
+/// Read a PROXY protocol v1 or v2 header, `None` when it carries no source address
+/// (v1 `UNKNOWN`, v2 `LOCAL` or a non-IP family)
+async fn read_header(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<SocketAddr>> {
+    // The shortest v1 header is longer than the v2 signature
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head).await?;
+
+    if &head == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        stream.read_exact(&mut fixed).await?;
+        let [version_command, family, len @ ..] = fixed;
+        let mut body = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut body).await?;
+        if version_command >> 4 != 2 {
+            return Err(invalid("unsupported PROXY protocol version"));
+        }
+        return match (version_command & 0x0f, family >> 4) {
+            (0, _) => Ok(None),
+            (1, 1) if body.len() >= 12 => {
+                let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&body[..4]).unwrap());
+                let port = u16::from_be_bytes([body[8], body[9]]);
+                Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+            }
+            (1, 2) if body.len() >= 36 => {
+                let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&body[..16]).unwrap());
+                let port = u16::from_be_bytes([body[32], body[33]]);
+                Ok(Some(SocketAddr::new(IpAddr::V6(ip), port)))
+            }
+            (1, 1 | 2) => Err(invalid("truncated PROXY protocol address")),
+            (1, _) => Ok(None),
+            _ => Err(invalid("unsupported PROXY protocol command")),
+        };
+    }
+
+    if !head.starts_with(b"PROXY ") {
+        return Err(invalid("no PROXY protocol header"));
+    }
+    let mut line = head.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY protocol header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    parse_v1(&line[..line.len() - 2]).ok_or_else(|| invalid("invalid PROXY protocol header"))
+}
+
+/// Parse "PROXY TCP4|TCP6 src dst sport dport" or "PROXY UNKNOWN ..."
+fn parse_v1(line: &[u8]) -> Option<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).ok()?;
+    let mut fields = line.split(' ').skip(1);
+    match fields.next()? {
+        "UNKNOWN" => Some(None),
+        "TCP4" | "TCP6" => {
+            let ip = fields.next()?.parse::<IpAddr>().ok()?;
+            let _dst = fields.next()?;
+            let port = fields.next()?.parse::<u16>().ok()?;
+            Some(Some(SocketAddr::new(ip, port)))
+        }
+        _ => None,
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> io::Result<Option<SocketAddr>> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut stream = bytes;
+        runtime.block_on(read_header(&mut stream))
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        read(bytes).unwrap_err().to_string()
+    }
+
+    /// v2 header with the given version/command and family bytes
+    fn v2(version_command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([version_command, family]);
+        header.extend((body.len() as u16).to_be_bytes());
+        header.extend(body);
+        header
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        assert_eq!(
+            read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        assert_eq!(
+            read(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n").unwrap(),
+            Some("[2001:db8::1]:4000".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn v1_unknown() {
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").unwrap(), None);
+        assert_eq!(
+            read(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn v1_invalid() {
+        for line in [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.1 70000 443\r\n"[..],
+            b"PROXY TCP4 not-an-ip 198.51.100.1 1 443\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 1 443\r\n",
+            b"PROXY TCP4 192.0.2.1\r\n",
+        ] {
+            assert_eq!(error(line), "invalid PROXY protocol header");
+        }
+        assert_eq!(error(b"GET / HTTP/1.1\r\n"), "no PROXY protocol header");
+    }
+
+    #[test]
+    fn v1_too_long() {
+        let mut line = b"PROXY TCP4 ".to_vec();
+        line.resize(200, b'1');
+        line.extend(b"\r\n");
+        assert_eq!(error(&line), "PROXY protocol header too long");
+    }
+
+    #[test]
+    fn v1_incomplete() {
+        let err = read(b"PROXY TCP4 192.0.2.1").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn v2_local() {
+        assert_eq!(read(&v2(0x20, 0x00, &[])).unwrap(), None);
+        // The addresses of a LOCAL header are ignored
+        assert_eq!(read(&v2(0x20, 0x11, &[0; 12])).unwrap(), None);
+    }
+
+    #[test]
+    fn v2_proxy_ipv4() {
+        let body = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        assert_eq!(
+            read(&v2(0x21, 0x11, &body)).unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn v2_proxy_ipv6() {
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut body = src.octets().to_vec();
+        body.extend(dst.octets());
+        body.extend(4000u16.to_be_bytes());
+        body.extend(443u16.to_be_bytes());
+        assert_eq!(
+            read(&v2(0x21, 0x21, &body)).unwrap(),
+            Some("[2001:db8::1]:4000".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn v2_proxy_unix() {
+        assert_eq!(read(&v2(0x21, 0x31, &[0; 216])).unwrap(), None);
+    }
+
+    #[test]
+    fn v2_truncated_address() {
+        assert_eq!(
+            error(&v2(0x21, 0x11, &[192, 0, 2, 1])),
+            "truncated PROXY protocol address"
+        );
+        assert_eq!(
+            error(&v2(0x21, 0x21, &[0; 20])),
+            "truncated PROXY protocol address"
+        );
+    }
+
+    #[test]
+    fn v2_short_body() {
+        // The length claims more than is sent
+        let mut header = v2(0x21, 0x11, &[0; 12]);
+        header.truncate(header.len() - 4);
+        let err = read(&header).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn v2_bad_version_or_command() {
+        assert_eq!(
+            error(&v2(0x11, 0x11, &[0; 12])),
+            "unsupported PROXY protocol version"
+        );
+        assert_eq!(
+            error(&v2(0x22, 0x11, &[0; 12])),
+            "unsupported PROXY protocol command"
+        );
+    }
+}
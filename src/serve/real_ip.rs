@@ -0,0 +1,171 @@
+use super::proxy_protocol::ClientAddr;
+use crate::{InstallConfig, RealIpHeader, ServeConfig};
+use axum::{
+    extract::State,
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+/// Address or network of a trusted reverse proxy
+#[derive(Clone, Copy)]
+pub(crate) struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn contains(&self, ip: IpAddr) -> bool {
+        // A dual-stack listener sees IPv4 peers as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) if self.addr.is_ipv4() => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            _ => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse an IP address or a network such as 10.0.0.0/8
+pub(crate) fn parse_cidr(s: &str) -> anyhow::Result<Cidr> {
+    let (addr, prefix) = match s.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (s, None),
+    };
+    let addr = addr
+        .parse::<IpAddr>()
+        .map_err(|_| anyhow::anyhow!("Invalid IP address: {addr}"))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => match prefix.parse::<u8>() {
+            Ok(prefix) if prefix <= max => prefix,
+            _ => anyhow::bail!("Network prefix must be between 0 and {max}"),
+        },
+        None => max,
+    };
+    Ok(Cidr { addr, prefix })
+}
+
+/// Replace the [`ClientAddr`] of requests from a trusted proxy with the address in the
+/// `--real-ip-header`, the other header is ignored
+pub(super) async fn middleware<B>(
+    State(conf): State<Arc<(ServeConfig, InstallConfig)>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let trusted = |ip: IpAddr| conf.0.trusted_proxy.iter().any(|net| net.contains(ip));
+    if let Some(&ClientAddr(peer)) = request.extensions().get::<ClientAddr>() {
+        if trusted(peer.ip()) {
+            if let Some(ip) = client_ip(request.headers(), conf.0.real_ip_header, trusted) {
+                request
+                    .extensions_mut()
+                    .insert(ClientAddr(SocketAddr::new(ip, peer.port())));
+            }
+        }
+    }
+    next.run(request).await
+}
+
+/// Client address of the header, for `X-Forwarded-For` the last entry that is not a
+/// trusted proxy
+fn client_ip(
+    headers: &HeaderMap,
+    header: RealIpHeader,
+    trusted: impl Fn(IpAddr) -> bool,
+) -> Option<IpAddr> {
+    match header {
+        RealIpHeader::XRealIp => headers.get("x-real-ip")?.to_str().ok()?.trim().parse().ok(),
+        RealIpHeader::XForwardedFor => {
+            let ips = headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(|ip| ip.trim().parse::<IpAddr>().ok())
+                .collect::<Option<Vec<IpAddr>>>()?;
+            // All entries trusted: the first one is the client
+            ips.iter()
+                .rev()
+                .find(|ip| !trusted(**ip))
+                .or(ips.first())
+                .copied()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn cidr_parse() {
+        let net = parse_cidr("10.0.0.0/8").unwrap();
+        assert!(net.contains(ip("10.255.0.1")));
+        assert!(!net.contains(ip("11.0.0.1")));
+        let host = parse_cidr("192.168.1.10").unwrap();
+        assert!(host.contains(ip("192.168.1.10")));
+        assert!(!host.contains(ip("192.168.1.11")));
+        let any = parse_cidr("0.0.0.0/0").unwrap();
+        assert!(any.contains(ip("203.0.113.7")));
+        assert!(!any.contains(ip("2001:db8::1")));
+        let v6 = parse_cidr("2001:db8::/32").unwrap();
+        assert!(v6.contains(ip("2001:db8:1::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+        assert!(parse_cidr("2001:db8::/129").is_err());
+        assert!(parse_cidr("10.0.0.0/x").is_err());
+        assert!(parse_cidr("proxy.lan").is_err());
+    }
+
+    #[test]
+    fn cidr_matches_ipv4_mapped_peer() {
+        let net = parse_cidr("10.0.0.0/8").unwrap();
+        assert!(net.contains(ip("::ffff:10.0.0.1")));
+        assert!(!net.contains(ip("::ffff:11.0.0.1")));
+        assert!(!parse_cidr("::1").unwrap().contains(ip("::ffff:0.0.0.1")));
+    }
+
+    #[test]
+    fn forwarded_for_walks_right_to_left() {
+        let net = parse_cidr("10.0.0.0/8").unwrap();
+        let trusted = |ip: IpAddr| net.contains(ip);
+        let xff = RealIpHeader::XForwardedFor;
+
+        // The client may forge entries left of the first untrusted one
+        let headers = forwarded(&["1.1.1.1, 203.0.113.7, 10.0.0.2", "10.0.0.3"]);
+        assert_eq!(client_ip(&headers, xff, trusted), Some(ip("203.0.113.7")));
+        // All entries trusted: the first one is the client
+        let headers = forwarded(&["10.0.0.9, 10.0.0.2"]);
+        assert_eq!(client_ip(&headers, xff, trusted), Some(ip("10.0.0.9")));
+        // A malformed entry discards the header
+        let headers = forwarded(&["203.0.113.7, unknown"]);
+        assert_eq!(client_ip(&headers, xff, trusted), None);
+        assert_eq!(client_ip(&HeaderMap::new(), xff, trusted), None);
+    }
+}
@@ -0,0 +1,107 @@
+use nix::libc;
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// Extended attribute holding the SELinux label of a file
+const XATTR: &[u8] = b"security.selinux\0";
+/// Enforcing status of selinuxfs, absent when SELinux is disabled
+const ENFORCE: &str = "/sys/fs/selinux/enforce";
+
+/// Check that a context looks like `user:role:type[:level]`
+pub(crate) fn parse_context(s: &str) -> anyhow::Result<String> {
+    let parts = s.splitn(4, ':').collect::<Vec<&str>>();
+    if parts.len() < 3 || parts[..3].iter().any(|part| part.is_empty()) {
+        anyhow::bail!("SELinux context must be user:role:type[:level]");
+    }
+    Ok(s.to_owned())
+}
+
+/// Whether SELinux is loaded and enforcing
+fn enforcing() -> bool {
+    std::fs::read_to_string(ENFORCE).is_ok_and(|enforce| enforce.trim() == "1")
+}
+
+/// Label the download directory and everything below it with `context` in the
+/// background, new files then inherit the label of their directory. Warns when
+/// SELinux is enforcing and no context is configured.
+pub(super) fn apply(path: PathBuf, context: Option<&str>) {
+    let Some(context) = context else {
+        if enforcing() {
+            log::warn!(
+                "SELinux is enforcing, files under {} keep their default label unless --selinux-context is set",
+                path.display()
+            );
+        }
+        return;
+    };
+    if !Path::new(ENFORCE).exists() {
+        log::warn!("SELinux is not enabled, ignoring --selinux-context");
+        return;
+    }
+    let context = context.to_owned();
+    let result = std::thread::Builder::new()
+        .name("selinux".to_owned())
+        .spawn(move || {
+            let (mut labeled, mut failed) = (0u64, 0u64);
+            label(&path, &context, &mut labeled, &mut failed);
+            match failed {
+                0 => log::info!(
+                    "SELinux: labeled {labeled} entries under {} as {context}",
+                    path.display()
+                ),
+                _ => log::warn!(
+                    "SELinux: labeled {labeled} entries under {} as {context}, {failed} failed",
+                    path.display()
+                ),
+            }
+        });
+    if let Err(err) = result {
+        log::error!("Failed to start the SELinux labeling: {err}");
+    }
+}
+
+/// Set the label of `path` and, for a directory, of its entries without following
+/// symlinks, directories first so new files inherit the label early
+fn label(path: &Path, context: &str, labeled: &mut u64, failed: &mut u64) {
+    match set_label(path, context) {
+        Ok(()) => *labeled += 1,
+        Err(err) => {
+            *failed += 1;
+            log::debug!("SELinux: failed to label {}: {err}", path.display());
+        }
+    }
+    let is_dir = std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir());
+    if !is_dir {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        label(&entry.path(), context, labeled, failed);
+    }
+}
+
+fn set_label(path: &Path, context: &str) -> std::io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // The kernel expects the terminating NUL as part of the value
+    let value = CString::new(context)?;
+    let value = value.as_bytes_with_nul();
+    // SAFETY: every pointer is valid and NUL terminated for the duration of the call
+    let result = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            XATTR.as_ptr().cast(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
@@ -0,0 +1,99 @@
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate, PrivateKey,
+};
+use std::sync::Arc;
+use x509_parser::{
+    extensions::{GeneralName, ParsedExtension},
+    prelude::{FromDer, X509Certificate},
+};
+
+/// Resolver of a certificate that never changes
+struct FixedCert(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for FixedCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+/// Certificate resolver that picks the certificate by the requested server name,
+/// falling back to the first one
+pub(super) struct SniResolver(Vec<(Vec<String>, Arc<dyn ResolvesServerCert>)>);
+
+impl SniResolver {
+    pub(super) fn new(
+        pairs: Vec<(Vec<Certificate>, PrivateKey)>,
+        ocsp_staple: bool,
+    ) -> anyhow::Result<Self> {
+        let mut entries = Vec::with_capacity(pairs.len());
+        for (certs, key) in pairs {
+            let names = names(&certs[0])?;
+            log::info!("TLS certificate for {}", names.join(", "));
+            let resolver: Arc<dyn ResolvesServerCert> = match ocsp_staple {
+                true => super::ocsp::resolver(certs, &key)?,
+                false => {
+                    let signing_key = rustls::sign::any_supported_type(&key)
+                        .map_err(|_| anyhow::anyhow!("Unsupported private key"))?;
+                    Arc::new(FixedCert(Arc::new(CertifiedKey::new(certs, signing_key))))
+                }
+            };
+            entries.push((names, resolver));
+        }
+        Ok(Self(entries))
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let index = client_hello
+            .server_name()
+            .map(|name| name.to_ascii_lowercase())
+            .and_then(|name| {
+                self.0
+                    .iter()
+                    .position(|(names, _)| names.iter().any(|n| matches(n, &name)))
+            })
+            .unwrap_or(0);
+        self.0[index].1.resolve(client_hello)
+    }
+}
+
+/// DNS names of the certificate, the common name when it has no subject alternative name
+pub(super) fn names(cert: &Certificate) -> anyhow::Result<Vec<String>> {
+    let (_, cert) = X509Certificate::from_der(&cert.0)
+        .map_err(|err| anyhow::anyhow!("Invalid certificate: {err}"))?;
+    let mut names = cert
+        .extensions()
+        .iter()
+        .filter_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::SubjectAlternativeName(san) => Some(&san.general_names),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) => Some(name.to_ascii_lowercase()),
+            _ => None,
+        })
+        .collect::<Vec<String>>();
+    if names.is_empty() {
+        names.extend(
+            cert.subject()
+                .iter_common_name()
+                .filter_map(|cn| cn.as_str().ok())
+                .map(|cn| cn.to_ascii_lowercase()),
+        );
+    }
+    Ok(names)
+}
+
+/// Match a server name against a certificate name, `*.` matches a single label
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => name
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => pattern == name,
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{InstallConfig, ServeConfig};
+use axum_server::Handle;
+use std::{sync::OnceLock, time::Instant};
+
+/// Server handle, for the number of open connections
+static HANDLE: OnceLock<Handle> = OnceLock::new();
+/// Time the server started
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Record the server handle once it is created
+pub(super) fn set_handle(handle: Handle) {
+    let _ = STARTED.set(Instant::now());
+    let _ = HANDLE.set(handle);
+}
+
+/// Log a snapshot of the runtime state, triggered by SIGUSR1
+pub(super) fn dump(serve_config: &ServeConfig, install_config: &InstallConfig) {
+    let connections = HANDLE.get().map_or(0, |handle| handle.connection_count());
+    let uptime = STARTED
+        .get()
+        .map_or(0, |started| started.elapsed().as_secs());
+    let free = crate::util::free_space(&install_config.download_path)
+        .map_or_else(|err| err.to_string(), |free| format!("{free} bytes free"));
+    #[cfg(target_os = "linux")]
+    let mounted = match super::mount::is_mountpoint(&install_config.mount_bind_download_path) {
+        true => "mounted",
+        false => "not mounted",
+    };
+    #[cfg(not(target_os = "linux"))]
+    let mounted = "unknown";
+    let drop_ins = InstallConfig::drop_ins()
+        .unwrap_or_default()
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<String>>();
+    log::info!(
+        "Runtime state\n  uptime: {uptime}s\n  connections: {connections}\n  thunder processes: {}\n  maintenance: {}\n  download path: {} ({free})\n  mount bind path: {} ({mounted})\n  serve config: {}\n  install config: {}{}\n  debug: {}",
+        super::pause::members(),
+        if super::maintenance::enabled() { "on" } else { "off" },
+        install_config.download_path.display(),
+        install_config.mount_bind_download_path.display(),
+        ServeConfig::path().display(),
+        InstallConfig::path().display(),
+        match drop_ins.is_empty() {
+            true => String::new(),
+            false => format!(", merged with {}", drop_ins.join(", ")),
+        },
+        serve_config.debug,
+    );
+}
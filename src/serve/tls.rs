@@ -0,0 +1,472 @@
+use anyhow::Context;
+use axum::{Extension, Json};
+use axum_server::{accept::Accept, tls_rustls::RustlsAcceptor};
+use rustls::{
+    server::{AllowAnyAuthenticatedClient, WantsServerCert},
+    version, Certificate, ConfigBuilder, PrivateKey, ProtocolVersion, RootCertStore, ServerConfig,
+    SignatureScheme, SupportedCipherSuite, SupportedProtocolVersion,
+};
+use std::{
+    fs::File, future::Future, io::BufReader, path::Path, pin::Pin, sync::Arc, time::UNIX_EPOCH,
+};
+use tokio_rustls::server::TlsStream;
+use tower_layer::Layer;
+use x509_parser::prelude::{ASN1Time, FromDer, Validity, X509Certificate};
+
+use super::clock::{self, Clock};
+use crate::{ServeConfig, TlsVersion};
+
+/// Message signed by the private key to check it matches the certificate
+const PROBE: &[u8] = b"thunder tls key probe";
+
+/// Load, verify and build the TLS server config, `None` when TLS is not configured
+pub(super) fn server_config(serve_config: &ServeConfig) -> anyhow::Result<Option<ServerConfig>> {
+    let Some(mut pairs) = load(serve_config)? else {
+        return Ok(None);
+    };
+
+    let builder = builder(serve_config)?;
+    let mut config = match (pairs.len(), serve_config.ocsp_staple) {
+        (1, true) => {
+            let (certs, key) = pairs.remove(0);
+            builder.with_cert_resolver(super::ocsp::resolver(certs, &key)?)
+        }
+        (1, false) => {
+            let (certs, key) = pairs.remove(0);
+            builder.with_single_cert(certs, key)?
+        }
+        _ => builder.with_cert_resolver(Arc::new(super::sni::SniResolver::new(
+            pairs,
+            serve_config.ocsp_staple,
+        )?)),
+    };
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Some(config))
+}
+
+/// Load and verify the TLS materials without building the server config
+pub(super) fn check(serve_config: &ServeConfig) -> anyhow::Result<()> {
+    match load(serve_config)? {
+        Some(_) => {
+            builder(serve_config)?;
+        }
+        None if serve_config.tls_client_ca.is_some() => {
+            anyhow::bail!("--tls-client-ca needs a TLS certificate")
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// Load and verify the TLS materials like the server does and print a summary of each
+/// certificate, failing when one is expired or not yet valid
+pub(super) fn inspect(serve_config: &ServeConfig, warn_days: i64) -> anyhow::Result<()> {
+    check(serve_config)?;
+    let Some(pairs) = load(serve_config)? else {
+        anyhow::bail!("TLS is not configured, give --tls-cert and --tls-key or --tls-pkcs12")
+    };
+    let now = clock::System.now().duration_since(UNIX_EPOCH)?.as_secs();
+    let now = ASN1Time::from_timestamp(now as i64)?;
+    let mut invalid = 0;
+    for (certs, _) in pairs.iter() {
+        println!(
+            "Certificate for {}",
+            super::sni::names(&certs[0])?.join(", ")
+        );
+        println!("  chain: {} certificates, key matches", certs.len());
+        for cert in certs {
+            let (_, parsed) = X509Certificate::from_der(&cert.0)
+                .map_err(|err| anyhow::anyhow!("Invalid certificate: {err}"))?;
+            let validity = parsed.validity();
+            let (status, valid) = expiry(validity, now, warn_days);
+            invalid += usize::from(!valid);
+            println!(
+                "  {}: {} to {}, {status}",
+                parsed.subject(),
+                validity.not_before,
+                validity.not_after
+            );
+        }
+    }
+    if let Some(ref ca) = serve_config.tls_client_ca {
+        println!("Client certificates verified by {}", ca.display());
+    }
+    if invalid > 0 {
+        anyhow::bail!("{invalid} certificates are expired or not yet valid");
+    }
+    Ok(())
+}
+
+/// Expiry status of a certificate at `now`, and whether it is valid then
+fn expiry(validity: &Validity, now: ASN1Time, warn_days: i64) -> (String, bool) {
+    if !validity.is_valid_at(now) {
+        let status = match validity.not_after < now {
+            true => "EXPIRED",
+            false => "NOT YET VALID",
+        };
+        return (status.to_owned(), false);
+    }
+    let left = (validity.not_after - now).unwrap_or_default().whole_days();
+    match left < warn_days {
+        true => (format!("expires in {left} days"), true),
+        false => (format!("{left} days left"), true),
+    }
+}
+
+/// Server config builder with the allowed protocol versions and cipher suites, requiring
+/// client certificates issued by `--tls-client-ca` when set
+fn builder(
+    serve_config: &ServeConfig,
+) -> anyhow::Result<ConfigBuilder<ServerConfig, WantsServerCert>> {
+    let versions: &[&'static SupportedProtocolVersion] = match serve_config.tls_min_version {
+        TlsVersion::Tls12 => &[&version::TLS13, &version::TLS12],
+        TlsVersion::Tls13 => &[&version::TLS13],
+    };
+
+    let mut suites = Vec::new();
+    for name in serve_config.tls_ciphers.iter() {
+        let suite = rustls::ALL_CIPHER_SUITES
+            .iter()
+            .find(|s| format!("{:?}", s.suite()).eq_ignore_ascii_case(name.trim()))
+            .with_context(|| {
+                let names = rustls::ALL_CIPHER_SUITES
+                    .iter()
+                    .map(|s| format!("{:?}", s.suite()))
+                    .collect::<Vec<String>>();
+                format!(
+                    "Unknown TLS cipher suite {name}, available: {}",
+                    names.join(", ")
+                )
+            })?;
+        suites.push(*suite);
+    }
+    if suites.is_empty() {
+        suites = rustls::DEFAULT_CIPHER_SUITES.to_vec();
+    }
+    suites.retain(|s| versions.contains(&s.version()));
+    if suites.is_empty() {
+        anyhow::bail!("No usable TLS cipher suite for the minimum TLS version");
+    }
+
+    let builder = ServerConfig::builder()
+        .with_cipher_suites(&suites)
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)?;
+    Ok(match serve_config.tls_client_ca {
+        Some(ref ca) => builder
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(load_ca(ca)?).boxed()),
+        None => builder.with_no_client_auth(),
+    })
+}
+
+/// Read the trusted client certificate authorities from a PEM file
+fn load_ca(ca: &Path) -> anyhow::Result<RootCertStore> {
+    let mut reader =
+        BufReader::new(File::open(ca).context(format!("Failed to open {}", ca.display()))?);
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut reader)? {
+        roots
+            .add(&Certificate(cert))
+            .map_err(|err| anyhow::anyhow!("Invalid CA certificate in {}: {err}", ca.display()))?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("No certificate found in {}", ca.display());
+    }
+    Ok(roots)
+}
+
+/// Negotiated TLS parameters of a connection
+#[derive(Clone)]
+pub(super) struct TlsInfo {
+    version: Option<ProtocolVersion>,
+    cipher: Option<SupportedCipherSuite>,
+    server_name: Option<String>,
+    client_cert: bool,
+    client_cn: Option<String>,
+}
+
+impl TlsInfo {
+    /// Common name of the verified client certificate
+    pub(super) fn client_cn(&self) -> Option<&str> {
+        self.client_cn.as_deref()
+    }
+}
+
+impl std::fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.version, self.cipher) {
+            (Some(version), Some(cipher)) => write!(f, "{:?} {:?}", version, cipher.suite()),
+            _ => f.write_str("unknown"),
+        }
+    }
+}
+
+/// TLS acceptor that adds the negotiated [`TlsInfo`] to the requests of each connection
+#[derive(Clone)]
+pub(super) struct InfoAcceptor(RustlsAcceptor);
+
+impl InfoAcceptor {
+    pub(super) fn new(acceptor: RustlsAcceptor) -> Self {
+        Self(acceptor)
+    }
+}
+
+impl<I, S> Accept<I, S> for InfoAcceptor
+where
+    RustlsAcceptor: Accept<I, S, Stream = TlsStream<I>, Service = S>,
+    <RustlsAcceptor as Accept<I, S>>::Future: Send + 'static,
+    I: Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = <Extension<TlsInfo> as Layer<S>>::Service;
+    type Future =
+        Pin<Box<dyn Future<Output = std::io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accept = self.0.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = accept.await.inspect_err(|err| {
+                if err.kind() == std::io::ErrorKind::TimedOut {
+                    log::debug!("Dropped a connection that did not finish the TLS handshake");
+                }
+            })?;
+            let (_, conn) = stream.get_ref();
+            let info = TlsInfo {
+                version: conn.protocol_version(),
+                cipher: conn.negotiated_cipher_suite(),
+                server_name: conn.server_name().map(str::to_owned),
+                client_cert: conn.peer_certificates().is_some(),
+                client_cn: conn
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .and_then(common_name),
+            };
+            Ok((stream, Extension(info).layer(service)))
+        })
+    }
+}
+
+/// GET /debug/tls handler, answers the TLS parameters of the current connection
+pub(super) async fn get_debug_tls(info: Option<Extension<TlsInfo>>) -> Json<serde_json::Value> {
+    Json(match info {
+        Some(Extension(info)) => serde_json::json!({
+            "tls": true,
+            "version": info.version.map(|version| format!("{version:?}")),
+            "cipher": info.cipher.map(|cipher| format!("{:?}", cipher.suite())),
+            "server_name": info.server_name,
+            "client_cert": info.client_cert,
+            "client_cn": info.client_cn,
+        }),
+        None => serde_json::json!({ "tls": false }),
+    })
+}
+
+/// Common name of the subject of a certificate
+fn common_name(cert: &Certificate) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(&cert.0).ok()?;
+    let cn = cert.subject().iter_common_name().next()?.as_str().ok()?;
+    Some(cn.to_owned())
+}
+
+/// Certificate chain and private key pairs
+type CertKeyPairs = Vec<(Vec<Certificate>, PrivateKey)>;
+
+/// Load and verify the certificate chains and private keys, `None` when TLS is not configured
+fn load(serve_config: &ServeConfig) -> anyhow::Result<Option<CertKeyPairs>> {
+    let pairs = match serve_config.tls_pkcs12 {
+        Some(ref bundle) => vec![load_pkcs12(
+            bundle,
+            serve_config
+                .tls_pkcs12_password
+                .as_deref()
+                .unwrap_or_default(),
+        )?],
+        None => {
+            let (certs, keys) = (&serve_config.tls_cert, &serve_config.tls_key);
+            if certs.len() != keys.len() {
+                anyhow::bail!(
+                    "--tls-cert and --tls-key must be given in pairs, got {} certificates and {} keys",
+                    certs.len(),
+                    keys.len()
+                );
+            }
+            certs
+                .iter()
+                .zip(keys)
+                .map(|(cert, key)| load_pem(cert, key))
+                .collect::<anyhow::Result<CertKeyPairs>>()?
+        }
+    };
+    if pairs.is_empty() {
+        return Ok(None);
+    }
+    for (certs, key) in pairs.iter() {
+        verify(certs, key)?;
+    }
+    Ok(Some(pairs))
+}
+
+/// Read the certificate chain and private key from a PKCS#12 bundle
+fn load_pkcs12(bundle: &Path, password: &str) -> anyhow::Result<(Vec<Certificate>, PrivateKey)> {
+    let data = std::fs::read(bundle).context(format!("Failed to open {}", bundle.display()))?;
+    let keystore = p12_keystore::KeyStore::from_pkcs12(
+        &data,
+        password,
+        p12_keystore::Pkcs12ImportPolicy::Strict,
+    )
+    .map_err(|err| anyhow::anyhow!("Failed to read {}: {err}", bundle.display()))?;
+    let (_, chain) = keystore.private_key_chain().context(format!(
+        "No certificate with a private key in {}",
+        bundle.display()
+    ))?;
+    let certs = chain
+        .certs()
+        .iter()
+        .map(|cert| Certificate(cert.as_der().to_vec()))
+        .collect();
+    Ok((certs, PrivateKey(chain.key().as_der().to_vec())))
+}
+
+/// Read the certificate chain and the first private key from PEM files
+fn load_pem(cert: &Path, key: &Path) -> anyhow::Result<(Vec<Certificate>, PrivateKey)> {
+    let mut reader =
+        BufReader::new(File::open(cert).context(format!("Failed to open {}", cert.display()))?);
+    let certs = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<Certificate>>();
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in {}", cert.display());
+    }
+
+    let mut reader =
+        BufReader::new(File::open(key).context(format!("Failed to open {}", key.display()))?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => break PrivateKey(key),
+            Some(_) => continue,
+            None => anyhow::bail!("No private key found in {}", key.display()),
+        }
+    };
+    Ok((certs, key))
+}
+
+/// Verify the key belongs to the leaf certificate and the chain is ordered leaf first
+fn verify(certs: &[Certificate], key: &PrivateKey) -> anyhow::Result<()> {
+    let parsed = certs
+        .iter()
+        .enumerate()
+        .map(|(i, cert)| {
+            X509Certificate::from_der(&cert.0)
+                .map(|(_, cert)| cert)
+                .map_err(|err| anyhow::anyhow!("Invalid certificate #{i} in chain: {err}"))
+        })
+        .collect::<anyhow::Result<Vec<X509Certificate>>>()?;
+
+    for (i, pair) in parsed.windows(2).enumerate() {
+        if pair[0].issuer() != pair[1].subject() {
+            anyhow::bail!(
+                "Certificate chain is out of order: #{} is issued by \"{}\", but #{} is \"{}\"",
+                i,
+                pair[0].issuer(),
+                i + 1,
+                pair[1].subject()
+            );
+        }
+    }
+
+    verify_key(&parsed[0], key)
+}
+
+/// Sign a probe with the private key and verify it with the certificate public key
+fn verify_key(cert: &X509Certificate, key: &PrivateKey) -> anyhow::Result<()> {
+    use ring::signature;
+
+    let signing_key = rustls::sign::any_supported_type(key)
+        .map_err(|_| anyhow::anyhow!("Unsupported private key"))?;
+    let signer = signing_key
+        .choose_scheme(&[
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ED25519,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ])
+        .context("Unsupported private key algorithm")?;
+    let algorithm: &dyn signature::VerificationAlgorithm = match signer.scheme() {
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
+        SignatureScheme::ED25519 => &signature::ED25519,
+        _ => &signature::RSA_PKCS1_2048_8192_SHA256,
+    };
+    let signature = signer.sign(PROBE)?;
+
+    let public_key = &cert.public_key().subject_public_key.data;
+    signature::UnparsedPublicKey::new(algorithm, public_key)
+        .verify(PROBE, &signature)
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "TLS cert/key mismatch: the private key does not belong to \"{}\"",
+                cert.subject()
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn at(clock: &clock::Fake) -> ASN1Time {
+        let secs = clock.now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        ASN1Time::from_timestamp(secs as i64).unwrap()
+    }
+
+    /// Validity of 90 days from the epoch
+    fn validity() -> Validity {
+        Validity {
+            not_before: ASN1Time::from_timestamp(0).unwrap(),
+            not_after: ASN1Time::from_timestamp(90 * DAY as i64).unwrap(),
+        }
+    }
+
+    #[test]
+    fn expiry_follows_the_clock() {
+        let clock = clock::Fake::at(SystemTime::UNIX_EPOCH + Duration::from_secs(DAY));
+        assert_eq!(
+            expiry(&validity(), at(&clock), 30),
+            (String::from("89 days left"), true)
+        );
+
+        clock.sleep(Duration::from_secs(70 * DAY));
+        assert_eq!(
+            expiry(&validity(), at(&clock), 30),
+            (String::from("expires in 19 days"), true)
+        );
+
+        clock.sleep(Duration::from_secs(20 * DAY));
+        assert_eq!(
+            expiry(&validity(), at(&clock), 30),
+            (String::from("EXPIRED"), false)
+        );
+    }
+
+    #[test]
+    fn expiry_before_validity() {
+        let validity = Validity {
+            not_before: ASN1Time::from_timestamp(10 * DAY as i64).unwrap(),
+            ..validity()
+        };
+        let clock = clock::Fake::at(SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            expiry(&validity, at(&clock), 30),
+            (String::from("NOT YET VALID"), false)
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    cmp::Ordering,
+    fs::OpenOptions,
+    io::{Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{Channel, CheckUpdateConfig, Running, SelfUpdateConfig};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/gngpp/thunder/releases";
+/// Timeout of each request to GitHub
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Upper bound of a downloaded release archive
+const MAX_ARCHIVE_SIZE: u64 = 64 << 20;
+/// Exit code of `check-update` when a newer release is available
+pub const UPDATE_AVAILABLE: i32 = 8;
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Semantic version of a release, `pre` is the part after `-`
+#[derive(PartialEq, Eq)]
+struct Version {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    pre: Option<String>,
+}
+
+impl Version {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches('v');
+        let (core, pre) = match s.split_once('-') {
+            Some((core, pre)) => (core, Some(pre.to_owned())),
+            None => (s, None),
+        };
+        let mut parts = core.split('.').map(|v| v.parse::<u64>().ok());
+        let version = Version {
+            major: parts.next()??,
+            minor: parts.next().unwrap_or(Some(0))?,
+            patch: parts.next().unwrap_or(Some(0))?,
+            pre,
+        };
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // A prerelease sorts before the release of the same version
+        (
+            self.major,
+            self.minor,
+            self.patch,
+            self.pre.is_none(),
+            &self.pre,
+        )
+            .cmp(&(
+                other.major,
+                other.minor,
+                other.patch,
+                other.pre.is_none(),
+                &other.pre,
+            ))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(ref pre) = self.pre {
+            write!(f, "-{pre}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Update the thunder binary from the GitHub releases
+pub struct SelfUpdate(pub SelfUpdateConfig);
+
+impl Running for SelfUpdate {
+    fn run(self) -> anyhow::Result<()> {
+        let current = Version::parse(env!("CARGO_PKG_VERSION")).context("Invalid crate version")?;
+        #[cfg(feature = "test-utils")]
+        let url = self.0.releases_url.as_deref().unwrap_or(RELEASES_URL);
+        #[cfg(not(feature = "test-utils"))]
+        let url = RELEASES_URL;
+        let mut releases = releases(url, self.0.channel, self.0.download_proxy.as_deref())?;
+        releases.sort_by(|(a, _), (b, _)| b.cmp(a));
+        let newest = releases.first().map(|(version, _)| version);
+        let major = |version: &Version| version.major == current.major || self.0.force;
+        if let Some(newest) = newest.filter(|v| **v > current && !major(v)) {
+            println!("Thunder {newest} is a major version update, use --force to update to it");
+        }
+
+        let Some((version, release)) = releases
+            .into_iter()
+            .find(|(version, _)| major(version))
+            .filter(|(version, _)| *version > current)
+        else {
+            println!("Thunder {current} is up to date");
+            return Ok(());
+        };
+        if self.0.check {
+            println!("Thunder {version} is available, current version {current}");
+            return Ok(());
+        }
+
+        let target = format!("{}-unknown-linux-musl", std::env::consts::ARCH);
+        let archive_name = archive_name(&release.tag_name, &target);
+        let asset_url = |name: &str| {
+            release
+                .assets
+                .iter()
+                .find(|asset| asset.name == name)
+                .map(|asset| asset.browser_download_url.as_str())
+                .context(format!("Release {} has no asset {name}", release.tag_name))
+        };
+        let archive_url = asset_url(&archive_name)?;
+        let checksum_url = asset_url(&format!("{archive_name}.sha256"))?;
+
+        println!("Downloading {archive_name}");
+        let archive = download(archive_url, self.0.download_proxy.as_deref())?;
+        let checksum =
+            String::from_utf8(download(checksum_url, self.0.download_proxy.as_deref())?)?;
+        verify_checksum(&archive, &checksum)?;
+
+        let binary = extract(&archive)?;
+        let exe = std::env::current_exe()?.canonicalize()?;
+        replace(&exe, &binary)?;
+
+        println!("Thunder updated from {current} to {version}");
+        println!(
+            "Restart the running daemon to use the new version: thunder stop && thunder start"
+        );
+        Ok(())
+    }
+}
+
+/// Report whether a newer release of thunder is available, across major versions,
+/// returns `true` when there is one
+pub fn check_update(config: CheckUpdateConfig, thunder: Option<String>) -> anyhow::Result<bool> {
+    #[cfg(feature = "test-utils")]
+    let url = config.releases_url.as_deref().unwrap_or(RELEASES_URL);
+    #[cfg(not(feature = "test-utils"))]
+    let url = RELEASES_URL;
+
+    let current = Version::parse(env!("CARGO_PKG_VERSION")).context("Invalid crate version")?;
+    let newest = releases(url, config.channel, config.download_proxy.as_deref())?
+        .into_iter()
+        .map(|(version, _)| version)
+        .max();
+    let available = match newest {
+        Some(newest) if newest > current => {
+            let major = match newest.major == current.major {
+                true => "",
+                false => ", a major version update",
+            };
+            println!("thunder: {current}, {newest} is available{major}");
+            true
+        }
+        _ => {
+            println!("thunder: {current}, up to date");
+            false
+        }
+    };
+    // The Xunlei package is downloaded from a fixed URL that carries no version
+    match thunder {
+        Some(version) => {
+            println!("Xunlei: {version} installed, its latest version is not published")
+        }
+        None => println!("Xunlei: not installed"),
+    }
+    Ok(available)
+}
+
+/// Published releases of the channel
+fn releases(
+    url: &str,
+    channel: Channel,
+    proxy: Option<&str>,
+) -> anyhow::Result<Vec<(Version, Release)>> {
+    let (agent, proxy) = crate::util::http_agent(url, proxy)?;
+    let response = agent
+        .get(url)
+        .timeout(TIMEOUT)
+        .set("Accept", "application/vnd.github+json")
+        .set("User-Agent", concat!("thunder/", env!("CARGO_PKG_VERSION")))
+        .call()
+        .context(match proxy {
+            Some(proxy) => format!(
+                "Failed to query the GitHub releases through {}",
+                crate::util::redact_proxy(&proxy)
+            ),
+            None => String::from("Failed to query the GitHub releases"),
+        })?;
+    let releases: Vec<Release> = serde_json::from_reader(response.into_reader())?;
+
+    Ok(releases
+        .into_iter()
+        .filter(|release| !release.draft)
+        .filter(|release| match channel {
+            Channel::Stable => !release.prerelease,
+            Channel::Prerelease => true,
+        })
+        .filter_map(|release| Version::parse(&release.tag_name).map(|v| (v, release)))
+        .collect())
+}
+
+fn download(url: &str, proxy: Option<&str>) -> anyhow::Result<Vec<u8>> {
+    let (agent, proxy) = crate::util::http_agent(url, proxy)?;
+    let response = agent
+        .get(url)
+        .timeout(TIMEOUT)
+        .call()
+        .context(match proxy {
+            Some(proxy) => format!(
+                "Failed to download {url} through {}",
+                crate::util::redact_proxy(&proxy)
+            ),
+            None => format!("Failed to download {url}"),
+        })?;
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_ARCHIVE_SIZE)
+        .read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Name of the release archive, the release workflow publishes it under the tag without its `v`
+fn archive_name(tag: &str, target: &str) -> String {
+    let tag = tag.strip_prefix('v').unwrap_or(tag);
+    format!("thunder-{tag}-{target}.tar.gz")
+}
+
+/// Compare the archive with the `shasum` output published next to it
+fn verify_checksum(archive: &[u8], checksum: &str) -> anyhow::Result<()> {
+    let expected = checksum
+        .split_whitespace()
+        .next()
+        .context("Empty checksum file")?;
+    let actual = ring::digest::digest(&ring::digest::SHA256, archive)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    if !actual.eq_ignore_ascii_case(expected) {
+        anyhow::bail!("Checksum mismatch: expected {expected}, got {actual}");
+    }
+    Ok(())
+}
+
+/// Read the thunder binary out of the release archive
+fn extract(archive: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut archive = tar::Archive::new(libflate::gzip::Decoder::new(archive)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry
+            .path()?
+            .file_name()
+            .is_some_and(|name| name == "thunder")
+        {
+            let mut binary = Vec::new();
+            entry.read_to_end(&mut binary)?;
+            return Ok(binary);
+        }
+    }
+    anyhow::bail!("No thunder binary in the release archive")
+}
+
+/// Write the new binary next to the executable and rename it over
+fn replace(exe: &std::path::Path, binary: &[u8]) -> anyhow::Result<()> {
+    let tmp = exe.with_file_name(".thunder.update");
+    let result = (|| -> anyhow::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o755)
+            .open(&tmp)?;
+        file.write_all(binary)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, exe)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result.context(format!("Failed to replace {}", exe.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_name_strips_v() {
+        assert_eq!(
+            archive_name("v1.0.1", "x86_64-unknown-linux-musl"),
+            "thunder-1.0.1-x86_64-unknown-linux-musl.tar.gz"
+        );
+        assert_eq!(
+            archive_name("1.0.1-beta.1", "aarch64-unknown-linux-musl"),
+            "thunder-1.0.1-beta.1-aarch64-unknown-linux-musl.tar.gz"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Daemon lifecycle against the fake thunder launcher. Mounting the download directory
+//! needs root, run with `sudo -E cargo test --features test-utils`.
+
+#![cfg(feature = "test-utils")]
+
+use std::{
+    net::TcpListener,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    time::{Duration, Instant},
+};
+
+const THUNDER: &str = env!("CARGO_BIN_EXE_thunder");
+const FAKE_THUNDER: &str = env!("CARGO_BIN_EXE_fake-thunder");
+
+/// Install config, pid file and directories of one server, stopped and removed on drop
+struct Sandbox {
+    dir: PathBuf,
+    port: u16,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("thunder-{name}-{}", std::process::id()));
+        for sub in ["config", "download", "mount"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let config_file = dir.join("thunder.conf");
+        std::fs::write(
+            &config_file,
+            format!(
+                "uid=0\ngid=0\nconfig_path={0}/config\ndownload_path={0}/download\nmount_bind_download_path={0}/mount\n",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&config_file, std::fs::Permissions::from_mode(0o600)).unwrap();
+        // Any free port, released again for the server to take
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        Self { dir, port }
+    }
+
+    fn pidfile(&self) -> PathBuf {
+        self.dir.join("thunder.pid")
+    }
+
+    fn thunder(&self, args: &[&str]) -> Output {
+        Command::new(THUNDER)
+            .arg("--config-file")
+            .arg(self.dir.join("thunder.conf"))
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn start(&self) -> Output {
+        let bind = format!("127.0.0.1:{}", self.port);
+        let pidfile = self.pidfile();
+        self.thunder(&[
+            "start",
+            "--bind",
+            &bind,
+            "--pidfile",
+            path_str(&pidfile),
+            "--thunder-bin",
+            FAKE_THUNDER,
+            "--thunder-web-bin",
+            FAKE_THUNDER,
+        ])
+    }
+
+    fn stop(&self) -> Output {
+        let pidfile = self.pidfile();
+        self.thunder(&["stop", "--pidfile", path_str(&pidfile)])
+    }
+
+    fn status(&self) -> Output {
+        let pidfile = self.pidfile();
+        self.thunder(&["status", "--pidfile", path_str(&pidfile)])
+    }
+
+    /// Body of `/healthz` once the server answers, within 10 seconds
+    fn healthz(&self) -> String {
+        let url = format!("http://127.0.0.1:{}/healthz", self.port);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match ureq::get(&url).call() {
+                Ok(response) => return response.into_string().unwrap(),
+                Err(err) if Instant::now() > deadline => panic!("{url}: {err}"),
+                Err(_) => std::thread::sleep(Duration::from_millis(200)),
+            }
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if self.pidfile().exists() {
+            self.stop();
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+fn is_root() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "exit {:?}\nstdout: {}\nstderr: {}",
+        output.status.code(),
+        stdout(output),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn start_healthz_status_stop() {
+    if !is_root() {
+        eprintln!("skipped, mounting the download directory needs root");
+        return;
+    }
+    let sandbox = Sandbox::new("lifecycle");
+
+    assert_success(&sandbox.start());
+    assert_eq!(sandbox.healthz(), "OK");
+
+    let status = sandbox.status();
+    assert_success(&status);
+    assert!(
+        stdout(&status).contains("Thunder is running"),
+        "{}",
+        stdout(&status)
+    );
+    // The bound address is kept next to the sandbox pid file
+    assert!(
+        stdout(&status).contains(&format!("Listening on: 127.0.0.1:{}", sandbox.port)),
+        "{}",
+        stdout(&status)
+    );
+
+    assert_success(&sandbox.stop());
+    assert!(!sandbox.dir.join("thunder.pid.listen").exists());
+    let status = sandbox.status();
+    assert_eq!(status.status.code(), Some(7));
+    assert!(
+        stdout(&status).contains("Thunder is not running"),
+        "{}",
+        stdout(&status)
+    );
+}